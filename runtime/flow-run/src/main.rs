@@ -1,9 +1,7 @@
 use clap::arg;
-use env_logger;
 use notred::*;
 use std::env;
 use std::fs;
-use std::time::Duration;
 
 fn main() {
     env_logger::init();
@@ -16,17 +14,8 @@ fn main() {
 
     let mut flow = notred::FlowState::new(flow_json.as_str()).expect("Failed to build the flow");
 
-    loop {
-        let res = flow.run_once(Duration::from_millis(100));
-        if let Ok(()) = res {
-            continue;
-        }
-        if let Err(Error::Terminate(_)) = res {
-            break;
-        }
-        if let Err(Error::Timeout(_)) = res {
-            continue;
-        }
-        res.expect("Failure while running flow");
+    let summary = flow.run();
+    if let StopReason::Error(e) = summary.reason {
+        panic!("Failure while running flow: {e:?}");
     }
 }
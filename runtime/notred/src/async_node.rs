@@ -0,0 +1,194 @@
+//! Support for nodes doing asynchronous work (e.g. I/O) on the Tokio runtime, enabled by the
+//! "async" feature.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use log::*;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+
+use crate::common::*;
+use crate::errors::Error;
+use crate::node::{NodeFunctionResult, NodeOutput};
+
+/// Asynchronous counterpart of Node::run.
+pub trait AsyncNode: Send + 'static {
+    fn run(
+        &mut self,
+        msg: Message,
+        input: usize,
+    ) -> impl Future<Output = NodeFunctionResult> + Send;
+}
+
+/// Runs an AsyncNode on the Tokio runtime on behalf of a Node.
+///
+/// The node creates the runner in Node::create and forwards its messages to it from Node::run.
+/// The messages are processed one at a time, in order, and the outputs are dispatched as coming
+/// from the node once each run completes.
+#[derive(Debug)]
+pub struct AsyncNodeRunner {
+    name: String,
+    sender: UnboundedSender<(Message, usize)>,
+    task: JoinHandle<()>,
+}
+
+impl AsyncNodeRunner {
+    /// Spawns the task running `node`, named `name` in the flow. Outputs to an index not below
+    /// `num_outputs` are dropped, like those of a synchronous node.
+    ///
+    /// Panics if not called in the context of a Tokio runtime, i.e. if the FlowState isn't built
+    /// within the runtime.
+    pub fn spawn<N: AsyncNode>(
+        mut node: N,
+        name: &str,
+        num_outputs: usize,
+        event_sender: Arc<Mutex<dyn EventSender>>,
+    ) -> AsyncNodeRunner {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(Message, usize)>();
+        let task_name = name.to_string();
+        let task = tokio::spawn(async move {
+            while let Some((msg, input)) = receiver.recv().await {
+                let input_id = msg.id;
+                let outputs = match node.run(msg, input).await {
+                    Ok(outputs) => outputs,
+                    Err(e) => {
+                        error!("Error in {task_name}: {e}");
+                        continue;
+                    }
+                };
+                for NodeOutput { index, message } in outputs {
+                    if index >= num_outputs {
+                        error!("{}", Error::InvalidPortIndex(task_name.clone(), index));
+                        continue;
+                    }
+                    let mut message = message.with_new_id();
+                    if message.parent_id.is_none() {
+                        message.parent_id = Some(input_id);
+                    }
+                    let event = Event::MessageFrom(MessageFrom {
+                        message,
+                        from: NodePort {
+                            name: task_name.clone(),
+                            index,
+                        },
+                    });
+                    // With OverflowPolicy::Block, dispatch() waits for space in the queue.
+                    // This mustn't block a worker thread of the runtime, which may be the
+                    // one needed by the task running the flow to make that space.
+                    let event_sender = event_sender.clone();
+                    let res = tokio::task::spawn_blocking(move || {
+                        event_sender.lock().unwrap().dispatch(event)
+                    })
+                    .await;
+                    match res {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => warn!("{task_name}: output dropped: {e}"),
+                        Err(e) => error!("{task_name}: output dropped: {e}"),
+                    }
+                }
+            }
+        });
+        AsyncNodeRunner {
+            name: name.to_string(),
+            sender,
+            task,
+        }
+    }
+
+    /// Queues the message for the AsyncNode. Returns no outputs, these are dispatched later.
+    pub fn run(&self, msg: &Message, input: usize) -> NodeFunctionResult {
+        self.sender
+            .send((msg.clone(), input))
+            .map_err(|_| Error::NodeStopped(self.name.clone()))?;
+        Ok(vec![])
+    }
+}
+
+impl Drop for AsyncNodeRunner {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    struct Echo;
+
+    impl AsyncNode for Echo {
+        async fn run(&mut self, msg: Message, input: usize) -> NodeFunctionResult {
+            Ok(vec![NodeOutput::new(input, Message::new(msg.data))])
+        }
+    }
+
+    /// Waits in dispatch() until the test lets the event through, like a full queue.
+    #[derive(Debug)]
+    struct WaitingSender {
+        ready: mpsc::Receiver<()>,
+        dispatched: Arc<Mutex<Vec<MessageData>>>,
+    }
+
+    impl EventSender for WaitingSender {
+        fn dispatch(&mut self, e: Event) -> Result<(), Error> {
+            self.ready.recv().unwrap();
+            if let Event::MessageFrom(mf) = e {
+                self.dispatched.lock().unwrap().push(mf.message.data);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_does_not_block_runtime() {
+        let (ready, ready_receiver) = mpsc::channel();
+        let dispatched = Arc::new(Mutex::new(Vec::new()));
+        let sender = WaitingSender {
+            ready: ready_receiver,
+            dispatched: dispatched.clone(),
+        };
+        let runner = AsyncNodeRunner::spawn(Echo, "echo1", 1, Arc::new(Mutex::new(sender)));
+        runner.run(&Message::new(MessageData::Int(1)), 0).unwrap();
+
+        // The node waits in dispatch() meanwhile, without blocking the only thread of the runtime
+        tokio::task::yield_now().await;
+        ready.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while dispatched.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*dispatched.lock().unwrap(), vec![MessageData::Int(1)]);
+    }
+    #[tokio::test]
+    async fn test_dispatch_invalid_output_index() {
+        let (ready, ready_receiver) = mpsc::channel();
+        let dispatched = Arc::new(Mutex::new(Vec::new()));
+        let sender = WaitingSender {
+            ready: ready_receiver,
+            dispatched: dispatched.clone(),
+        };
+        let runner = AsyncNodeRunner::spawn(Echo, "echo1", 1, Arc::new(Mutex::new(sender)));
+        // Echo outputs to the index of the input, and has a single output
+        runner.run(&Message::new(MessageData::Int(1)), 1).unwrap();
+        runner.run(&Message::new(MessageData::Int(2)), 0).unwrap();
+
+        ready.send(()).unwrap();
+        ready.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while dispatched.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // The messages are run in order, so the first one was dropped
+        assert_eq!(*dispatched.lock().unwrap(), vec![MessageData::Int(2)]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of time of a flow, driving the timers of the nodes.
+pub trait Clock: Debug + Send + Sync {
+    /// Time elapsed since the clock started.
+    fn now(&self) -> Duration;
+
+    /// A virtual clock only advances when told to, so FlowState doesn't wait for its timers.
+    fn is_virtual(&self) -> bool {
+        false
+    }
+
+    /// Advances a virtual clock to `time`, if it is later than now. Used by FlowState::run_for;
+    /// other clocks ignore it.
+    fn advance_to(&self, _time: Duration) {}
+}
+
+/// Clock following the real time, used by default.
+#[derive(Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock following the time of the Tokio runtime, for flows run with FlowState::run_async.
+///
+/// Unlike SystemClock, it follows the paused time of the runtime in tests.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct TokioClock {
+    start: tokio::time::Instant,
+}
+
+#[cfg(feature = "async")]
+impl TokioClock {
+    pub fn new() -> TokioClock {
+        TokioClock {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Default for TokioClock {
+    fn default() -> Self {
+        TokioClock::new()
+    }
+}
+
+#[cfg(feature = "async")]
+impl Clock for TokioClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock which only advances when VirtualClock::advance is called, to test time-based flows
+/// without waiting. The clones of a VirtualClock share the same time.
+///
+/// The timers which elapse are fired by the next calls to FlowState::run_once.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn is_virtual(&self) -> bool {
+        true
+    }
+
+    fn advance_to(&self, time: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = (*now).max(time);
+    }
+}
+
+/// Identifies a timer started with NodeClock::start_timer.
+pub type TimerId = u64;
+
+#[derive(Debug)]
+struct Timer {
+    node_index: usize,
+    period: Option<Duration>,
+}
+
+/// Timers of all the nodes of a flow, ordered by deadline, then by the order they were started.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    timers: BTreeMap<(Duration, TimerId), Timer>,
+    next_id: TimerId,
+}
+
+impl Timers {
+    pub(crate) fn next_deadline(&self) -> Option<Duration> {
+        self.timers.keys().next().map(|(deadline, _)| *deadline)
+    }
+
+    /// Removes the earliest timer which has elapsed at `now`, restarting it if it is periodic.
+    /// Returns the index of its node and its id.
+    pub(crate) fn pop_due(&mut self, now: Duration) -> Option<(usize, TimerId)> {
+        let (&(deadline, id), _) = self.timers.iter().next()?;
+        if deadline > now {
+            return None;
+        }
+        let timer = self.timers.remove(&(deadline, id)).unwrap();
+        let node_index = timer.node_index;
+        if let Some(period) = timer.period {
+            self.timers.insert((deadline + period, id), timer);
+        }
+        Some((node_index, id))
+    }
+}
+
+/// Clock given to a node (see Node::create), to read the time of the flow and start timers.
+///
+/// When a timer elapses, FlowState calls Node::on_timer on the thread running the flow, like it
+/// calls Node::run for a message.
+#[derive(Debug, Clone)]
+pub struct NodeClock {
+    clock: Arc<dyn Clock>,
+    timers: Arc<Mutex<Timers>>,
+    node_index: usize,
+}
+
+impl NodeClock {
+    pub(crate) fn new(
+        clock: Arc<dyn Clock>,
+        timers: Arc<Mutex<Timers>>,
+        node_index: usize,
+    ) -> NodeClock {
+        NodeClock {
+            clock,
+            timers,
+            node_index,
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Starts a timer elapsing after `delay`, then every `period` if set.
+    pub fn start_timer(&self, delay: Duration, period: Option<Duration>) -> TimerId {
+        let mut timers = self.timers.lock().unwrap();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        let timer = Timer {
+            node_index: self.node_index,
+            period,
+        };
+        timers.timers.insert((self.now() + delay, id), timer);
+        id
+    }
+
+    pub fn cancel_timer(&self, id: TimerId) {
+        self.timers
+            .lock()
+            .unwrap()
+            .timers
+            .retain(|(_, timer_id), _| *timer_id != id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timers() {
+        let clock = VirtualClock::new();
+        let timers = Arc::new(Mutex::new(Timers::default()));
+        let node0 = NodeClock::new(Arc::new(clock.clone()), timers.clone(), 0);
+        let node1 = NodeClock::new(Arc::new(clock.clone()), timers.clone(), 1);
+        let periodic = node0.start_timer(Duration::from_millis(100), Some(Duration::from_secs(1)));
+        let once = node1.start_timer(Duration::from_millis(100), None);
+        let cancelled = node1.start_timer(Duration::from_millis(50), None);
+        node1.cancel_timer(cancelled);
+
+        let pop_due = || timers.lock().unwrap().pop_due(clock.now());
+        assert_eq!(pop_due(), None);
+        clock.advance(Duration::from_millis(100));
+        // Same deadline: in the order the timers were started
+        assert_eq!(pop_due(), Some((0, periodic)));
+        assert_eq!(pop_due(), Some((1, once)));
+        assert_eq!(pop_due(), None);
+        assert_eq!(
+            timers.lock().unwrap().next_deadline(),
+            Some(Duration::from_millis(1100))
+        );
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(node0.now(), Duration::from_millis(2100));
+        assert_eq!(pop_due(), Some((0, periodic)));
+        assert_eq!(pop_due(), Some((0, periodic)));
+        assert_eq!(pop_due(), None);
+    }
+}
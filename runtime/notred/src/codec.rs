@@ -0,0 +1,249 @@
+//! Encoding of MessageData into binary formats (CBOR, MessagePack), and decoding of these formats
+//! into JSON values, which are then mapped to MessageData of the requested type by
+//! conversion::json_to_message_data.
+//!
+//! Byte strings are decoded into base64-encoded JSON strings, matching the JSON representation of
+//! Binary data.
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use ciborium::value::Value as CborValue;
+use rmpv::Value as MsgPackValue;
+use serde_json::{Map, Number, Value};
+
+use crate::message::MessageData as MD;
+use crate::message::*;
+
+/// CBOR tag of a date/time string (RFC 8949, section 3.4.1)
+const CBOR_TAG_DATE_TIME: u64 = 0;
+/// CBOR tag of an epoch-based date/time (RFC 8949, section 3.4.2)
+const CBOR_TAG_EPOCH: u64 = 1;
+/// MessagePack extension type of timestamps
+const MSGPACK_EXT_TIMESTAMP: i8 = -1;
+
+fn sorted_entries(d: &Dict) -> Vec<(&String, &MessageData)> {
+    let mut entries: Vec<(&String, &MessageData)> = d.data.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+fn timestamp_to_json(t: DateTime<Utc>) -> Value {
+    Value::String(t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+fn float_to_json(f: f64) -> Result<Value, String> {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or_else(|| format!("{f} can't be represented"))
+}
+
+pub(crate) fn data_to_cbor(md: &MessageData) -> Result<Vec<u8>, String> {
+    let mut res = Vec::new();
+    ciborium::ser::into_writer(&data_to_cbor_value(md), &mut res).map_err(|e| e.to_string())?;
+    Ok(res)
+}
+
+fn data_to_cbor_value(md: &MessageData) -> CborValue {
+    match md {
+        MD::Text(t) => CborValue::Text(t.value.clone()),
+        MD::Binary(b) => CborValue::Bytes(b.value.clone()),
+        MD::Int(i) => CborValue::Integer((*i).into()),
+        MD::FixedInt(i) => match u64::try_from(i.value) {
+            Ok(u) => CborValue::Integer(u.into()),
+            Err(_) => CborValue::Integer((i.value as i64).into()),
+        },
+        MD::Float(f) => CborValue::Float(*f),
+        MD::Bool(b) => CborValue::Bool(*b),
+        MD::Null => CborValue::Null,
+        MD::Timestamp(t) => CborValue::Tag(
+            CBOR_TAG_DATE_TIME,
+            Box::new(CborValue::Text(
+                t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            )),
+        ),
+        MD::Dict(d) => CborValue::Map(
+            sorted_entries(d)
+                .into_iter()
+                .map(|(key, value)| (CborValue::Text(key.clone()), data_to_cbor_value(value)))
+                .collect(),
+        ),
+        MD::List(l) => CborValue::Array(l.iter().map(data_to_cbor_value).collect()),
+    }
+}
+
+/// Decodes CBOR data nested at most `max_depth` levels deep.
+pub(crate) fn cbor_to_json(bytes: &[u8], max_depth: usize) -> Result<Value, String> {
+    let value: CborValue = ciborium::de::from_reader_with_recursion_limit(bytes, max_depth)
+        .map_err(|e| format!("invalid CBOR: {e}"))?;
+    cbor_value_to_json(&value)
+}
+
+fn cbor_value_to_json(value: &CborValue) -> Result<Value, String> {
+    match value {
+        CborValue::Integer(i) => {
+            let i = i128::from(*i);
+            if let Ok(i) = i64::try_from(i) {
+                Ok(Value::Number(i.into()))
+            } else if let Ok(u) = u64::try_from(i) {
+                Ok(Value::Number(u.into()))
+            } else {
+                Err(format!("integer {i} is out of range"))
+            }
+        }
+        CborValue::Bytes(b) => Ok(Value::String(base64::encode(b))),
+        CborValue::Float(f) => float_to_json(*f),
+        CborValue::Text(t) => Ok(Value::String(t.clone())),
+        CborValue::Bool(b) => Ok(Value::Bool(*b)),
+        CborValue::Null => Ok(Value::Null),
+        CborValue::Tag(CBOR_TAG_EPOCH, inner) => {
+            let seconds = match inner.as_ref() {
+                CborValue::Integer(i) => i128::from(*i) as f64,
+                CborValue::Float(f) => *f,
+                _ => return Err("invalid epoch-based date/time".to_string()),
+            };
+            if !seconds.is_finite() {
+                return Err(format!(
+                    "epoch-based date/time {seconds} is not a finite number"
+                ));
+            }
+            // Rounding the nanoseconds may carry over into the next second
+            let mut secs = seconds.floor();
+            let mut nanos = ((seconds - secs) * 1e9).round();
+            if nanos >= 1e9 {
+                secs += 1.0;
+                nanos = 0.0;
+            }
+            // Out of range values saturate, and are rejected by timestamp_opt()
+            match Utc.timestamp_opt(secs as i64, nanos as u32).single() {
+                Some(t) => Ok(timestamp_to_json(t)),
+                None => Err(format!("epoch-based date/time {seconds} is out of range")),
+            }
+        }
+        // Other tags only give additional meaning to the value
+        CborValue::Tag(_, inner) => cbor_value_to_json(inner),
+        CborValue::Array(arr) => Ok(Value::Array(
+            arr.iter()
+                .map(cbor_value_to_json)
+                .collect::<Result<_, _>>()?,
+        )),
+        CborValue::Map(entries) => {
+            let mut obj = Map::new();
+            for (key, value) in entries {
+                let key = match key {
+                    CborValue::Text(t) => t.clone(),
+                    CborValue::Integer(i) => i128::from(*i).to_string(),
+                    _ => return Err(format!("unsupported map key {key:?}")),
+                };
+                obj.insert(key, cbor_value_to_json(value)?);
+            }
+            Ok(Value::Object(obj))
+        }
+        _ => Err(format!("unsupported value {value:?}")),
+    }
+}
+
+pub(crate) fn data_to_msgpack(md: &MessageData) -> Result<Vec<u8>, String> {
+    let mut res = Vec::new();
+    rmpv::encode::write_value(&mut res, &data_to_msgpack_value(md)).map_err(|e| e.to_string())?;
+    Ok(res)
+}
+
+fn data_to_msgpack_value(md: &MessageData) -> MsgPackValue {
+    match md {
+        MD::Text(t) => MsgPackValue::from(t.value.as_str()),
+        MD::Binary(b) => MsgPackValue::Binary(b.value.clone()),
+        MD::Int(i) => MsgPackValue::from(*i),
+        MD::FixedInt(i) => match u64::try_from(i.value) {
+            Ok(u) => MsgPackValue::from(u),
+            Err(_) => MsgPackValue::from(i.value as i64),
+        },
+        MD::Float(f) => MsgPackValue::F64(*f),
+        MD::Bool(b) => MsgPackValue::Boolean(*b),
+        MD::Null => MsgPackValue::Nil,
+        MD::Timestamp(t) => {
+            // 96-bit timestamp format: nanoseconds, then seconds
+            let mut data = t.timestamp_subsec_nanos().to_be_bytes().to_vec();
+            data.extend(t.timestamp().to_be_bytes());
+            MsgPackValue::Ext(MSGPACK_EXT_TIMESTAMP, data)
+        }
+        MD::Dict(d) => MsgPackValue::Map(
+            sorted_entries(d)
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        MsgPackValue::from(key.as_str()),
+                        data_to_msgpack_value(value),
+                    )
+                })
+                .collect(),
+        ),
+        MD::List(l) => MsgPackValue::Array(l.iter().map(data_to_msgpack_value).collect()),
+    }
+}
+
+/// Decodes MessagePack data nested at most `max_depth` levels deep.
+pub(crate) fn msgpack_to_json(bytes: &[u8], max_depth: usize) -> Result<Value, String> {
+    let mut reader = bytes;
+    let value = rmpv::decode::read_value_with_max_depth(&mut reader, max_depth)
+        .map_err(|e| format!("invalid MessagePack: {e}"))?;
+    if !reader.is_empty() {
+        return Err("invalid MessagePack: unexpected data after the value".to_string());
+    }
+    msgpack_value_to_json(&value)
+}
+
+fn msgpack_timestamp(data: &[u8]) -> Option<DateTime<Utc>> {
+    let (seconds, nanos) = match data.len() {
+        4 => (u32::from_be_bytes(data.try_into().ok()?) as i64, 0),
+        8 => {
+            let value = u64::from_be_bytes(data.try_into().ok()?);
+            ((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32)
+        }
+        12 => (
+            i64::from_be_bytes(data[4..].try_into().ok()?),
+            u32::from_be_bytes(data[..4].try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    Utc.timestamp_opt(seconds, nanos).single()
+}
+
+fn msgpack_value_to_json(value: &MsgPackValue) -> Result<Value, String> {
+    match value {
+        MsgPackValue::Nil => Ok(Value::Null),
+        MsgPackValue::Boolean(b) => Ok(Value::Bool(*b)),
+        MsgPackValue::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Ok(Value::Number(i.into())),
+            (None, Some(u)) => Ok(Value::Number(u.into())),
+            _ => Err(format!("integer {i} is out of range")),
+        },
+        MsgPackValue::F32(f) => float_to_json(*f as f64),
+        MsgPackValue::F64(f) => float_to_json(*f),
+        MsgPackValue::String(s) => match s.as_str() {
+            Some(s) => Ok(Value::String(s.to_string())),
+            None => Err("string is not valid UTF-8".to_string()),
+        },
+        MsgPackValue::Binary(b) => Ok(Value::String(base64::encode(b))),
+        MsgPackValue::Array(arr) => Ok(Value::Array(
+            arr.iter()
+                .map(msgpack_value_to_json)
+                .collect::<Result<_, _>>()?,
+        )),
+        MsgPackValue::Map(entries) => {
+            let mut obj = Map::new();
+            for (key, value) in entries {
+                let key = match key {
+                    MsgPackValue::String(s) if s.is_str() => s.as_str().unwrap().to_string(),
+                    MsgPackValue::Integer(i) => i.to_string(),
+                    _ => return Err(format!("unsupported map key {key}")),
+                };
+                obj.insert(key, msgpack_value_to_json(value)?);
+            }
+            Ok(Value::Object(obj))
+        }
+        MsgPackValue::Ext(MSGPACK_EXT_TIMESTAMP, data) => match msgpack_timestamp(data) {
+            Some(t) => Ok(timestamp_to_json(t)),
+            None => Err("invalid timestamp".to_string()),
+        },
+        MsgPackValue::Ext(ext_type, _) => Err(format!("unsupported extension type {ext_type}")),
+    }
+}
@@ -1,9 +1,13 @@
 use core::fmt;
-use std::fmt::{Debug, Formatter};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::errors::Error;
 pub use crate::message::{MessageConverter, MessageData};
 use crate::MessageType;
 
@@ -23,7 +27,71 @@ pub struct NodePort {
     pub index: usize,
 }
 
-pub type Message = MessageData;
+pub type MessageId = u64;
+
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_message_id() -> MessageId {
+    NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Message is the envelope around the MessageData passed between the nodes.
+///
+/// Besides the payload, it carries an id unique within the process, the id of the message it was
+/// produced from (if any), the creation time, an optional topic and arbitrary headers. The
+/// envelope is preserved when the payload is converted on a connection, except for the id: each
+/// destination of a node output receives a copy with its own id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub id: MessageId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<MessageId>,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    pub data: MessageData,
+}
+
+impl Message {
+    pub fn new(data: MessageData) -> Message {
+        Message {
+            id: next_message_id(),
+            parent_id: None,
+            timestamp: Utc::now(),
+            topic: None,
+            headers: HashMap::new(),
+            data,
+        }
+    }
+
+    /// Gives the message a new id, e.g. for a copy of it.
+    pub fn with_new_id(mut self) -> Message {
+        self.id = next_message_id();
+        self
+    }
+
+    pub fn with_topic(mut self, topic: &str) -> Message {
+        self.topic = Some(topic.to_string());
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Message {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(topic) = &self.topic {
+            write!(f, "[{topic}] ")?;
+        }
+        // Forwarded to keep the precision, which limits the binary data shown
+        Display::fmt(&self.data, f)
+    }
+}
 
 // FIXME: rename to Message
 #[derive(Debug, Clone, PartialEq)]
@@ -46,7 +114,14 @@ pub enum Event {
 }
 
 pub trait EventSender: fmt::Debug + Send {
-    fn dispatch(&mut self, e: Event);
+    /// Queues the event. Fails if the queue is full and its OverflowPolicy is Error.
+    fn dispatch(&mut self, e: Event) -> Result<(), Error>;
+}
+
+/// Conversion requested explicitly for a connection in the flow file, overriding the implicit one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExplicitConversion {
+    pub name: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -57,8 +132,16 @@ pub struct Connection {
     pub dest: NodePort,
     #[serde(skip)]
     pub conversion: Option<MessageConverter>,
-    #[serde(skip)]
+    /// Type of the messages delivered to the destination. May be declared in the flow file for
+    /// destinations which accept any type; otherwise it is set to the input type of the
+    /// destination node when the flow is checked.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
     pub dest_type: Option<MessageType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub convert: Option<ExplicitConversion>,
+    /// Allows a lossy implicit conversion on this connection when the flow is strict.
+    #[serde(default)]
+    pub allow_lossy: bool,
 }
 
 impl Debug for Connection {
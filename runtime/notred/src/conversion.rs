@@ -1,82 +1,467 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use log::*;
+use serde_json::{Map, Number, Value};
+
+use crate::codec;
+use crate::conversion_path;
 use crate::message::FindConversionError::*;
 use crate::message::MessageData as MD;
 use crate::message::MessageType as MT;
 use crate::message::*;
 
-pub fn find(src: &MT, dst: &MT) -> FindConversionResult {
+/// Built-in converters are plain functions, wrapped into a MessageConverter when they are found.
+type BuiltinConverter = fn(src: &MessageData, dst: &MT) -> ConversionResult;
+/// Built-in converter which looks up the conversions of nested values or decodes binary data, so
+/// needs the limit of the nesting depth given to the lookup.
+type NestedConverter = fn(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult;
+type FindBuiltinResult = Result<MessageConverter, FindConversionError>;
+
+fn with_max_depth(conv: NestedConverter, max_depth: usize) -> MessageConverter {
+    Arc::new(move |src: &MessageData, dst: &MT| conv(src, dst, max_depth))
+}
+
+/// Finds the implicit conversion from `src` to `dst`, possibly going through several steps.
+pub fn find(src: &MT, dst: &MT, max_depth: usize) -> FindConversionResult {
+    conversion_path::find_path(src, dst, max_depth).map(|path| path.converter)
+}
+
+/// Checks that dictionary and list types aren't nested deeper than `max_depth`.
+///
+/// Finding a conversion and converting the values recurses through the nested types, so this is
+/// checked (without recursion) before the lookup.
+pub(crate) fn check_nesting_depth(mt: &MT, max_depth: usize) -> Result<(), FindConversionError> {
+    let mut stack: Vec<(&MT, Vec<&str>, usize)> = vec![(mt, Vec::new(), 0)];
+    while let Some((mt, key_path, depth)) = stack.pop() {
+        let nested: Vec<(&MT, Option<&str>)> = match mt {
+            MT::Dict(schema) => schema.iter().map(|(k, t)| (t, Some(k.as_str()))).collect(),
+            MT::List(element_type) => vec![(element_type.as_ref(), Some("[]"))],
+            // Union members don't add to the key path, but still add to the nesting
+            MT::Optional(inner) => vec![(inner.as_ref(), None)],
+            MT::OneOf(members) => members.iter().map(|t| (t, None)).collect(),
+            _ => continue,
+        };
+        for (nested_type, key) in nested {
+            let mut nested_path = key_path.clone();
+            nested_path.extend(key);
+            if depth + 1 > max_depth {
+                return Err(NestingTooDeep {
+                    key_path: nested_path.join("."),
+                    max_depth,
+                });
+            }
+            stack.push((nested_type, nested_path, depth + 1));
+        }
+    }
+    Ok(())
+}
+
+/// Finds the direct (single step) implicit conversion from `src` to `dst`.
+pub(crate) fn find_builtin(src: &MT, dst: &MT, max_depth: usize) -> FindBuiltinResult {
+    if let Some(res) = find_dynamic(src, dst, max_depth) {
+        return res;
+    }
     match dst {
-        MT::Text(dst_content_type) => to_text(src, dst_content_type),
-        MT::Binary(dst_content_type) => to_binary(src, dst_content_type),
-        MT::Int => to_int(src),
-        MT::Float => to_float(src),
-        MT::Dict(dst_schema) => to_dict(src, dst_schema),
+        MT::Text(dst_content_type) => to_text(src, dst_content_type, max_depth),
+        MT::Binary(dst_content_type) => to_binary(src, dst_content_type, max_depth),
+        MT::Int => to_int(src, max_depth),
+        MT::FixedInt(dst_int_type) => to_fixed_int(src, dst_int_type, max_depth),
+        MT::Float => to_float(src, max_depth),
+        MT::Bool => to_bool(src, max_depth),
+        MT::Null => to_null(src, max_depth),
+        MT::Timestamp => to_timestamp(src, max_depth),
+        MT::Dict(dst_schema) => to_dict(src, dst_schema, max_depth),
+        MT::List(dst_element_type) => to_list(src, dst_element_type, max_depth),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
+    }
+}
+
+/// Returns the members of a union type: the types listed in OneOf, or the inner type and Null
+/// for Optional.
+fn union_members(mt: &MT) -> Option<Vec<MT>> {
+    match mt {
+        MT::Optional(inner) => Some(vec![inner.as_ref().clone(), MT::Null]),
+        MT::OneOf(members) => Some(members.clone()),
+        _ => None,
+    }
+}
+
+/// Finds the conversions involving Any and union types, for which the actual converter can only be
+/// chosen at runtime based on the value. Returns None if neither type is one of those.
+fn find_dynamic(src: &MT, dst: &MT, max_depth: usize) -> Option<FindBuiltinResult> {
+    if *dst == MT::Any {
+        return Some(Ok(Arc::new(identity)));
+    }
+    if *src == MT::Any {
+        // Any value may turn out to be convertible
+        return Some(Ok(with_max_depth(convert_by_value_type, max_depth)));
+    }
+    if let Some(src_members) = union_members(src) {
+        if src == dst {
+            return Some(Ok(Arc::new(identity)));
+        }
+        // Every possible value must be convertible
+        for member in &src_members {
+            if let Err(e) = find_builtin(member, dst, max_depth) {
+                return Some(Err(NoImplicitConversionDetailed(format!(
+                    "Couldn't convert {member} (member of {src}) to type {dst}: {e}"
+                ))));
+            }
+        }
+        return Some(Ok(with_max_depth(convert_by_value_type, max_depth)));
+    }
+    if let Some(dst_members) = union_members(dst) {
+        // At least one of the members must be reachable
+        if dst_members
+            .iter()
+            .any(|m| find_builtin(src, m, max_depth).is_ok())
+        {
+            return Some(Ok(with_max_depth(to_union, max_depth)));
+        }
+        return Some(Err(NoImplicitConversionDetailed(format!(
+            "None of the members of {dst} can be obtained from type {src}"
+        ))));
+    }
+    None
+}
+
+/// Finds the explicit conversion with the given name between the given types.
+///
+/// Explicit conversions cover the cases which are too ambiguous or lossy to happen implicitly:
+///
+/// - utf8: Text <-> Binary, encoding the text as UTF-8
+/// - base64: Binary <-> Text, with the text holding base64-encoded data
+/// - hex: Binary <-> Text, with the text holding hex-encoded data
+/// - json: any type -> Text, or Text -> any type, using the JSON representation of the data
+/// - round: Float -> Int, rounding to the nearest integer
+/// - truncate: Float -> Int, rounding towards zero
+/// - le_i32: Int <-> Binary, as a 4-byte little-endian signed integer
+pub fn find_explicit(name: &str, src: &MT, dst: &MT, max_depth: usize) -> FindConversionResult {
+    check_nesting_depth(src, max_depth)?;
+    check_nesting_depth(dst, max_depth)?;
+    let res: Option<BuiltinConverter> = match (name, src, dst) {
+        ("utf8", MT::Text(_), MT::Binary(_)) => Some(text_to_binary_utf8),
+        ("utf8", MT::Binary(_), MT::Text(_)) => Some(binary_to_text_utf8),
+        ("base64", MT::Binary(_), MT::Text(_)) => Some(binary_to_text_base64),
+        ("base64", MT::Text(_), MT::Binary(_)) => Some(text_base64_to_binary),
+        ("hex", MT::Binary(_), MT::Text(_)) => Some(binary_to_text_hex),
+        ("hex", MT::Text(_), MT::Binary(_)) => Some(text_hex_to_binary),
+        ("json", _, MT::Text(_)) => Some(any_to_text_json),
+        ("json", MT::Text(_), _) => Some(text_json_to_data),
+        ("round", MT::Float, MT::Int) => Some(float_to_int_round),
+        ("truncate", MT::Float, MT::Int) => Some(float_to_int),
+        ("le_i32", MT::Int, MT::Binary(_)) => Some(int_to_binary_le_i32),
+        ("le_i32", MT::Binary(_), MT::Int) => Some(binary_le_i32_to_int),
+        ("utf8" | "base64" | "hex" | "json" | "round" | "truncate" | "le_i32", _, _) => None,
+        _ => {
+            return Err(InvalidExplicitConversion(format!(
+                "unknown conversion '{name}'"
+            )))
+        }
+    };
+    match res {
+        Some(conv) => Ok(Arc::new(conv)),
+        None => Err(InvalidExplicitConversion(format!(
+            "conversion '{name}' can't convert {src} to {dst}"
+        ))),
     }
 }
 
-fn to_text(src: &MT, dst_content_type: &TextContentType) -> FindConversionResult {
+fn to_text(src: &MT, dst_content_type: &TextContentType, max_depth: usize) -> FindBuiltinResult {
     match src {
-        MT::Text(_) => Ok(identity),
-        MT::Binary(_) => Err(NoImplicitConversion),
-        MT::Int => Ok(int_to_text),
-        MT::Float => Ok(float_to_text),
+        MT::Text(src_content_type) => match (src_content_type, dst_content_type) {
+            _ if src_content_type == dst_content_type => Ok(Arc::new(identity)),
+            // Any text is valid plain text
+            (_, TextContentType::Plain) => Ok(Arc::new(relabel_text)),
+            (TextContentType::Base64, TextContentType::Hex)
+            | (TextContentType::Hex, TextContentType::Base64)
+            | (TextContentType::Json, TextContentType::Yaml)
+            | (TextContentType::Yaml, TextContentType::Json) => Ok(Arc::new(transcode_text)),
+            _ => Err(NoImplicitConversion),
+        },
+        MT::Binary(src_content_type) => match (src_content_type, dst_content_type) {
+            (_, TextContentType::Base64) => Ok(Arc::new(binary_to_text_base64)),
+            (_, TextContentType::Hex) => Ok(Arc::new(binary_to_text_hex)),
+            (
+                BinaryContentType::Cbor | BinaryContentType::MessagePack,
+                TextContentType::Json | TextContentType::Yaml,
+            ) => Ok(with_max_depth(encoded_binary_to_text, max_depth)),
+            (BinaryContentType::Cbor | BinaryContentType::MessagePack, _) => {
+                Err(NoImplicitConversionDetailed(format!(
+                    "Can't convert {} to text with content-type {}. Try setting content-type to JSON or YAML.",
+                    src,
+                    MT::Text(dst_content_type.clone())
+                )))
+            }
+            (_, TextContentType::Plain) => Ok(Arc::new(binary_to_text_utf8)),
+            _ => Err(NoImplicitConversion),
+        },
+        MT::Int => scalar_to_text(int_to_text, dst_content_type),
+        MT::FixedInt(_) => scalar_to_text(int_to_text, dst_content_type),
+        MT::Float => scalar_to_text(float_to_text, dst_content_type),
+        MT::Bool => scalar_to_text(bool_to_text, dst_content_type),
+        MT::Null => match dst_content_type {
+            TextContentType::Json => Ok(Arc::new(null_to_text_json)),
+            _ => Err(NoImplicitConversionDetailed(
+                format!("Can't convert null to text with content-type {}. Try setting content-type to JSON.", MT::Text(dst_content_type.clone()))
+            ))
+        },
+        MT::Timestamp => scalar_to_text(timestamp_to_text, dst_content_type),
         MT::Dict(_) => match dst_content_type {
-            TextContentType::Json => Ok(dict_to_text_json),
+            TextContentType::Json => Ok(Arc::new(dict_to_text_json)),
+            TextContentType::Yaml => Ok(Arc::new(data_to_text_yaml)),
+            _ => Err(NoImplicitConversionDetailed(
+                format!("Can't convert dictionary to text with content-type {}. Try setting content-type to JSON or YAML.", MT::Text(dst_content_type.clone()))
+            ))
+        },
+        MT::List(element_type) => match dst_content_type {
+            TextContentType::Json => Ok(Arc::new(list_to_text_json)),
+            TextContentType::Yaml => Ok(Arc::new(data_to_text_yaml)),
+            TextContentType::Csv if is_csv_row_type(element_type) => Ok(Arc::new(list_to_text_csv)),
+            TextContentType::Csv => Err(NoImplicitConversionDetailed(format!(
+                "Can't convert list of {element_type} to CSV. Only lists of dictionaries with simple values can be converted."
+            ))),
             _ => Err(NoImplicitConversionDetailed(
-                format!("Can't convert dictionary to text with content-type {}. Try setting content-type to JSON.", MT::Text(dst_content_type.clone()))
+                format!("Can't convert list to text with content-type {}. Try setting content-type to JSON or YAML.", MT::Text(dst_content_type.clone()))
             ))
         },
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
     }
 }
 
-fn to_binary(src: &MT, _dst_content_type: &BinaryContentType) -> FindConversionResult {
+/// Simple values are written as plain text with `to_plain`, or as JSON. Other content types
+/// (e.g. base64) would need the text to be encoded, which isn't done implicitly.
+fn scalar_to_text(
+    to_plain: BuiltinConverter,
+    dst_content_type: &TextContentType,
+) -> FindBuiltinResult {
+    match dst_content_type {
+        TextContentType::Plain => Ok(Arc::new(to_plain)),
+        TextContentType::Json => Ok(Arc::new(any_to_text_json)),
+        _ => Err(NoImplicitConversion),
+    }
+}
+
+fn to_binary(
+    src: &MT,
+    dst_content_type: &BinaryContentType,
+    max_depth: usize,
+) -> FindBuiltinResult {
     match src {
-        MT::Text(_) => Err(NoImplicitConversion),
-        MT::Binary(_) => Ok(identity),
-        MT::Int => Err(NoImplicitConversion),
-        MT::Float => Err(NoImplicitConversion),
+        MT::Binary(src_content_type) => match (src_content_type, dst_content_type) {
+            _ if src_content_type == dst_content_type => Ok(Arc::new(identity)),
+            // Unstructured data may be relabeled to or from any content type
+            (BinaryContentType::Unknown | BinaryContentType::OctetStream, _)
+            | (_, BinaryContentType::Unknown | BinaryContentType::OctetStream) => {
+                Ok(Arc::new(relabel_binary))
+            }
+            (
+                BinaryContentType::Cbor | BinaryContentType::MessagePack,
+                BinaryContentType::Cbor | BinaryContentType::MessagePack,
+            ) => Ok(with_max_depth(encoded_binary_to_binary, max_depth)),
+        },
+        MT::Text(src_content_type) => match (src_content_type, dst_content_type) {
+            (TextContentType::Base64, _) => Ok(Arc::new(text_base64_to_binary)),
+            (TextContentType::Hex, _) => Ok(Arc::new(text_hex_to_binary)),
+            (
+                TextContentType::Json | TextContentType::Yaml,
+                BinaryContentType::Cbor | BinaryContentType::MessagePack,
+            ) => Ok(Arc::new(text_to_encoded_binary)),
+            (_, BinaryContentType::Cbor | BinaryContentType::MessagePack) => {
+                to_encoded_binary(dst_content_type)
+            }
+            (TextContentType::Plain, _) => Ok(Arc::new(text_to_binary_utf8)),
+            _ => Err(NoImplicitConversion),
+        },
+        MT::Int => to_encoded_binary(dst_content_type),
+        MT::FixedInt(_) => to_encoded_binary(dst_content_type),
+        MT::Float => to_encoded_binary(dst_content_type),
+        MT::Bool => to_encoded_binary(dst_content_type),
+        MT::Null => to_encoded_binary(dst_content_type),
+        MT::Timestamp => to_encoded_binary(dst_content_type),
+        MT::Dict(_) => to_encoded_binary(dst_content_type),
+        MT::List(_) => to_encoded_binary(dst_content_type),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
+    }
+}
+
+/// Finds the encoder of a value into binary data with the given content type.
+fn to_encoded_binary(dst_content_type: &BinaryContentType) -> FindBuiltinResult {
+    match dst_content_type {
+        BinaryContentType::Cbor => Ok(Arc::new(data_to_binary_cbor)),
+        BinaryContentType::MessagePack => Ok(Arc::new(data_to_binary_msgpack)),
+        _ => Err(NoImplicitConversion),
+    }
+}
+
+/// Finds the decoder of binary data with the given content type into a value.
+fn from_encoded_binary(
+    src_content_type: &BinaryContentType,
+    max_depth: usize,
+) -> FindBuiltinResult {
+    match src_content_type {
+        BinaryContentType::Cbor => Ok(with_max_depth(binary_cbor_to_data, max_depth)),
+        BinaryContentType::MessagePack => Ok(with_max_depth(binary_msgpack_to_data, max_depth)),
+        _ => Err(NoImplicitConversion),
+    }
+}
+
+fn to_int(src: &MT, max_depth: usize) -> FindBuiltinResult {
+    match src {
+        MT::Text(_) => Ok(Arc::new(text_to_int)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Ok(Arc::new(identity)),
+        MT::FixedInt(_) => Ok(Arc::new(fixed_int_to_int)),
+        MT::Float => Ok(Arc::new(float_to_int)),
+        MT::Bool => Ok(Arc::new(bool_to_int)),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Ok(Arc::new(timestamp_to_int)),
+        MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
+    }
+}
+
+fn to_fixed_int(src: &MT, dst_int_type: &IntType, max_depth: usize) -> FindBuiltinResult {
+    match src {
+        MT::Text(_) => Ok(Arc::new(text_to_fixed_int)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Ok(Arc::new(int_to_fixed_int)),
+        MT::FixedInt(src_int_type) if src_int_type == dst_int_type => Ok(Arc::new(identity)),
+        MT::FixedInt(_) => Ok(Arc::new(int_to_fixed_int)),
+        MT::Float => Ok(Arc::new(float_to_fixed_int)),
+        MT::Bool => Err(NoImplicitConversion),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Err(NoImplicitConversion),
         MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
     }
 }
 
-fn to_int(src: &MT) -> FindConversionResult {
+fn to_float(src: &MT, max_depth: usize) -> FindBuiltinResult {
     match src {
-        MT::Text(_) => Ok(text_to_int),
-        MT::Binary(_) => Err(NoImplicitConversion),
-        MT::Int => Ok(identity),
-        MT::Float => Ok(float_to_int),
+        MT::Text(_) => Ok(Arc::new(text_to_float)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Ok(Arc::new(int_to_float)),
+        MT::FixedInt(_) => Ok(Arc::new(int_to_float)),
+        MT::Float => Ok(Arc::new(identity)),
+        MT::Bool => Err(NoImplicitConversion),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Err(NoImplicitConversion),
         MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
     }
 }
 
-fn to_float(src: &MT) -> FindConversionResult {
+fn to_bool(src: &MT, max_depth: usize) -> FindBuiltinResult {
     match src {
-        MT::Text(_) => Ok(text_to_float),
-        MT::Binary(_) => Err(NoImplicitConversion),
-        MT::Int => Ok(int_to_float),
-        MT::Float => Ok(identity),
+        MT::Text(_) => Ok(Arc::new(text_to_bool)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Ok(Arc::new(int_to_bool)),
+        MT::FixedInt(_) => Err(NoImplicitConversion),
+        MT::Float => Err(NoImplicitConversion),
+        MT::Bool => Ok(Arc::new(identity)),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Err(NoImplicitConversion),
+        MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
+    }
+}
+
+fn to_null(src: &MT, max_depth: usize) -> FindBuiltinResult {
+    match src {
+        MT::Text(TextContentType::Json) => Ok(Arc::new(text_json_to_data)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Null => Ok(Arc::new(identity)),
+        _ => Err(NoImplicitConversion),
+    }
+}
+
+fn to_timestamp(src: &MT, max_depth: usize) -> FindBuiltinResult {
+    match src {
+        MT::Text(_) => Ok(Arc::new(text_to_timestamp)),
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Ok(Arc::new(int_to_timestamp)),
+        MT::FixedInt(_) => Err(NoImplicitConversion),
+        MT::Float => Err(NoImplicitConversion),
+        MT::Bool => Err(NoImplicitConversion),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Ok(Arc::new(identity)),
         MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
+    }
+}
+
+fn to_dict(src: &MT, dst_schema: &DictSchema, max_depth: usize) -> FindBuiltinResult {
+    match src {
+        MT::Text(src_content_type) => match src_content_type {
+            TextContentType::Json => Ok(Arc::new(text_json_to_data)),
+            TextContentType::Yaml => Ok(Arc::new(text_yaml_to_data)),
+            _ => Err(NoImplicitConversionDetailed(
+                format!("Can't convert text with content-type {} to dictionary. Try setting content-type to JSON or YAML.", MT::Text(src_content_type.clone()))
+            ))
+        },
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
+        MT::Int => Err(NoImplicitConversion),
+        MT::FixedInt(_) => Err(NoImplicitConversion),
+        MT::Float => Err(NoImplicitConversion),
+        MT::Bool => Err(NoImplicitConversion),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Err(NoImplicitConversion),
+        MT::Dict(src_schema) => from_dict_to_dict(src_schema, dst_schema, max_depth),
+        MT::List(_) => Err(NoImplicitConversion),
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
     }
 }
 
-fn to_dict(src: &MT, dst_schema: &DictSchema) -> FindConversionResult {
+fn to_list(src: &MT, dst_element_type: &MT, max_depth: usize) -> FindBuiltinResult {
     match src {
-        MT::Text(_) => Err(NoImplicitConversion),
-        MT::Binary(_) => Err(NoImplicitConversion),
+        MT::Text(src_content_type) => match src_content_type {
+            TextContentType::Json => Ok(Arc::new(text_json_to_data)),
+            TextContentType::Yaml => Ok(Arc::new(text_yaml_to_data)),
+            TextContentType::Csv if is_csv_row_type(dst_element_type) => Ok(with_max_depth(text_csv_to_list, max_depth)),
+            TextContentType::Csv => Err(NoImplicitConversionDetailed(format!(
+                "Can't convert CSV to list of {dst_element_type}. CSV can only be converted to lists of dictionaries with simple values."
+            ))),
+            _ => Err(NoImplicitConversionDetailed(
+                format!("Can't convert text with content-type {} to list. Try setting content-type to JSON or YAML.", MT::Text(src_content_type.clone()))
+            ))
+        },
+        MT::Binary(src_content_type) => from_encoded_binary(src_content_type, max_depth),
         MT::Int => Err(NoImplicitConversion),
+        MT::FixedInt(_) => Err(NoImplicitConversion),
         MT::Float => Err(NoImplicitConversion),
-        MT::Dict(src_schema) => from_dict_to_dict(src_schema, dst_schema),
+        MT::Bool => Err(NoImplicitConversion),
+        MT::Null => Err(NoImplicitConversion),
+        MT::Timestamp => Err(NoImplicitConversion),
+        MT::Dict(_) => Err(NoImplicitConversion),
+        MT::List(src_element_type) => match find_builtin(src_element_type, dst_element_type, max_depth) {
+            Ok(_) => Ok(with_max_depth(list_to_list, max_depth)),
+            Err(e) => Err(NoImplicitConversionDetailed(format!(
+                "Couldn't convert list elements from type {src_element_type} to type {dst_element_type}: {e}"
+            ))),
+        },
+        MT::Any | MT::Optional(_) | MT::OneOf(_) => unreachable!("handled by find_dynamic()"),
     }
 }
 
-fn from_dict_to_dict(src: &DictSchema, dst: &DictSchema) -> FindConversionResult {
+fn from_dict_to_dict(src: &DictSchema, dst: &DictSchema, max_depth: usize) -> FindBuiltinResult {
     for (key, mt_dst) in dst {
         match src.get(key) {
+            // Optional keys may be missing
+            None if matches!(mt_dst, MT::Optional(_)) => continue,
             None => return Err(NoImplicitConversionDetailed(
                 format!("Key '{key}' is present in the destination dictionary but not in the source dictionary")
             )),
             Some(mt_src) => {
-                // FIXME: for deeply nested dictionaries, this recursion may overflow the stack.
-                match find_conversion(mt_src, mt_dst) {
+                // The nesting depth was checked by the caller, so this recursion is bounded.
+                match find_builtin(mt_src, mt_dst, max_depth) {
                     // found some conversion, continue with the next key
                     Ok(_) => continue,
                     Err(e) => return Err(NoImplicitConversionDetailed(
@@ -87,33 +472,372 @@ fn from_dict_to_dict(src: &DictSchema, dst: &DictSchema) -> FindConversionResult
         }
     }
     // All the keys of the destination dict can be obtained from the keys in the source dict.
-    // TODO: Should there be a warning if some key from the source dict is dropped?
-    Ok(dict_to_dict)
+    // Keys of the source dict missing from the destination are dropped, see find_loss().
+    Ok(with_max_depth(dict_to_dict, max_depth))
+}
+
+/// Describes the information lost by the direct implicit conversion from `src` to `dst`.
+/// Returns None if the conversion is lossless.
+///
+/// Conversions which may fail, like parsing text, aren't considered lossy: they either produce
+/// the exact value or an error.
+pub(crate) fn find_loss(src: &MT, dst: &MT, max_depth: usize) -> Option<String> {
+    if *dst == MT::Any || src == dst {
+        return None;
+    }
+    // The value may be of any member type, or of any simple type for Any
+    let src_members = match src {
+        MT::Any => Some(vec![
+            MT::Int,
+            MT::FixedInt(IntType::U64),
+            MT::Float,
+            MT::Timestamp,
+        ]),
+        _ => union_members(src),
+    };
+    if let Some(src_members) = src_members {
+        let losses = src_members
+            .iter()
+            .filter(|member| find_builtin(member, dst, max_depth).is_ok())
+            .filter_map(|member| {
+                find_loss(member, dst, max_depth).map(|loss| format!("{member} values: {loss}"))
+            })
+            .collect();
+        return join_losses(losses);
+    }
+    // Checked before the union members, which may include a lossless Null
+    if is_decoded(src) && has_dict(dst) {
+        return Some("keys missing from the dictionary schema are dropped".to_string());
+    }
+    if let Some(dst_members) = union_members(dst) {
+        // to_union prefers the members which can hold the value without losing information
+        let reachable: Vec<&MT> = dst_members
+            .iter()
+            .filter(|member| find_builtin(src, member, max_depth).is_ok())
+            .collect();
+        if reachable
+            .iter()
+            .any(|member| find_loss(src, member, max_depth).is_none())
+        {
+            return None;
+        }
+        return reachable
+            .first()
+            .and_then(|member| find_loss(src, member, max_depth));
+    }
+    match (src, dst) {
+        (MT::Float, MT::Int | MT::FixedInt(_)) => {
+            Some("the fractional part is truncated".to_string())
+        }
+        (MT::Int | MT::FixedInt(IntType::U64), MT::Float) => {
+            Some("integers larger than 2^53 lose precision".to_string())
+        }
+        (MT::Timestamp, MT::Int) => {
+            Some("the part of the timestamp below a millisecond is dropped".to_string())
+        }
+        (MT::Dict(src_schema), MT::Dict(dst_schema)) => {
+            let mut losses = Vec::new();
+            let mut dropped_keys: Vec<&String> = src_schema
+                .keys()
+                .filter(|key| !dst_schema.contains_key(*key))
+                .collect();
+            if !dropped_keys.is_empty() {
+                dropped_keys.sort();
+                losses.push(format!("keys {dropped_keys:?} are dropped"));
+            }
+            let mut keys: Vec<&String> = dst_schema.keys().collect();
+            keys.sort();
+            for key in keys {
+                if let Some(mt_src) = src_schema.get(key) {
+                    if let Some(loss) = find_loss(mt_src, &dst_schema[key], max_depth) {
+                        losses.push(format!("key '{key}': {loss}"));
+                    }
+                }
+            }
+            join_losses(losses)
+        }
+        (MT::List(src_element_type), MT::List(dst_element_type)) => {
+            find_loss(src_element_type, dst_element_type, max_depth)
+                .map(|loss| format!("list elements: {loss}"))
+        }
+        _ => None,
+    }
+}
+
+/// Whether values of `mt` are decoded from text or binary data, with the keys of the dictionaries
+/// in it not bound to a schema.
+fn is_decoded(mt: &MT) -> bool {
+    matches!(
+        mt,
+        MT::Text(TextContentType::Json | TextContentType::Yaml)
+            | MT::Binary(BinaryContentType::Cbor | BinaryContentType::MessagePack)
+    )
+}
+
+/// Whether values of `mt` may contain dictionaries.
+fn has_dict(mt: &MT) -> bool {
+    match mt {
+        MT::Dict(_) => true,
+        MT::List(element_type) => has_dict(element_type),
+        _ => union_members(mt).is_some_and(|members| members.iter().any(has_dict)),
+    }
+}
+
+fn join_losses(losses: Vec<String>) -> Option<String> {
+    if losses.is_empty() {
+        None
+    } else {
+        Some(losses.join("; "))
+    }
 }
 
 pub fn identity(src: &MessageData, _dst: &MT) -> ConversionResult {
     Ok(src.clone())
 }
 
-fn dict_to_dict(_src: &MessageData, _dst: &MT) -> ConversionResult {
-    unimplemented!();
+fn dict_to_dict(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let (MD::Dict(src_dict), MT::Dict(dst_schema)) = (src, dst) {
+        let mut data = HashMap::new();
+        for (key, mt_dst) in dst_schema {
+            let (md_src, mt_src) = match (src_dict.data.get(key), src_dict.schema.get(key)) {
+                (Some(md_src), Some(mt_src)) => (md_src, mt_src),
+                // Optional keys may be missing from the data
+                (None, _) if matches!(mt_dst, MT::Optional(_)) => continue,
+                // A missing optional value is converted as null
+                (None, Some(mt_src @ MT::Optional(_))) => (&MD::Null, mt_src),
+                (Some(_), None) => {
+                    return Err(ConversionError::new(
+                        src,
+                        dst,
+                        &format!(
+                            "the schema of the source dictionary has no entry for key '{key}'"
+                        ),
+                    )
+                    .in_key(key))
+                }
+                _ => {
+                    return Err(ConversionError::new(
+                        src,
+                        dst,
+                        "key is missing in the source dictionary",
+                    )
+                    .in_key(key))
+                }
+            };
+            // The schemas were already checked by find_builtin(), so this lookup is expected to succeed.
+            let conv = find_builtin(mt_src, mt_dst, max_depth)
+                .map_err(|e| ConversionError::new(md_src, mt_dst, &e.to_string()).in_key(key))?;
+            data.insert(
+                key.clone(),
+                conv(md_src, mt_dst).map_err(|e| e.in_key(key))?,
+            );
+        }
+
+        let mut dropped_keys: Vec<&String> = src_dict
+            .data
+            .keys()
+            .filter(|key| !dst_schema.contains_key(*key))
+            .collect();
+        if !dropped_keys.is_empty() {
+            dropped_keys.sort();
+            debug!("Keys dropped in dictionary conversion: {dropped_keys:?}");
+        }
+
+        return Ok(MD::Dict(Dict {
+            data,
+            schema: dst_schema.clone(),
+        }));
+    }
+    unreachable!("src should be a Dict and dst should be a Dict type")
+}
+
+fn list_to_list(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let (MD::List(src_list), MT::List(dst_element_type)) = (src, dst) {
+        let mut res = Vec::with_capacity(src_list.len());
+        for (index, element) in src_list.iter().enumerate() {
+            let key = index.to_string();
+            // Lists don't carry the element type, so the converter is looked up for each element
+            // based on the type of its value.
+            let element_type = value_type(element, dst_element_type);
+            let conv = find_builtin(&element_type, dst_element_type, max_depth).map_err(|e| {
+                ConversionError::new(element, dst_element_type, &e.to_string()).in_key(&key)
+            })?;
+            res.push(conv(element, dst_element_type).map_err(|e| e.in_key(&key))?);
+        }
+        return Ok(MD::List(res));
+    }
+    unreachable!("src should be a List and dst should be a List type")
+}
+
+/// Converts a value of Any or union type, finding the converter based on the type of the value.
+fn convert_by_value_type(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    let src_type = value_type(src, dst);
+    let conv = find_builtin(&src_type, dst, max_depth)
+        .map_err(|e| ConversionError::new(src, dst, &e.to_string()))?;
+    conv(src, dst)
+}
+
+/// Converts a value to the first member of the destination union type which accepts it.
+///
+/// The member of the same type as the value is preferred, then the members which can hold the value
+/// without losing information, otherwise the members are tried in order.
+fn to_union(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let Some(mut members) = union_members(dst) {
+        let src_type = value_type(src, dst);
+        if members.contains(&src_type) {
+            return Ok(src.clone());
+        }
+        // The sort is stable, so the order of the members is kept otherwise
+        members.sort_by_key(|member| find_loss(&src_type, member, max_depth).is_some());
+        let mut last_err = None;
+        for member in &members {
+            if let Ok(conv) = find_builtin(&src_type, member, max_depth) {
+                match conv(src, member) {
+                    Ok(res) => return Ok(res),
+                    Err(e) => last_err = Some(e),
+                }
+            }
+        }
+        return Err(match last_err {
+            Some(e) => {
+                ConversionError::new(src, dst, &format!("no member accepts the value ({e})"))
+            }
+            None => ConversionError::new(src, dst, "no member accepts the value"),
+        });
+    }
+    unreachable!("dst should be a union type")
+}
+
+/// Determines the type of the given value.
+///
+/// The element type of an empty list can't be determined from the value, so `hint` (normally the
+/// type the value is being converted to) is used instead. The elements of a list holding values of
+/// different types are of the union of these types, in the order they first appear.
+pub(crate) fn value_type(md: &MessageData, hint: &MT) -> MT {
+    match md {
+        MD::Text(t) => MT::Text(t.content_type.clone()),
+        MD::Binary(b) => MT::Binary(b.content_type.clone()),
+        MD::Int(_) => MT::Int,
+        MD::FixedInt(i) => MT::FixedInt(i.int_type),
+        MD::Float(_) => MT::Float,
+        MD::Bool(_) => MT::Bool,
+        MD::Null => MT::Null,
+        MD::Timestamp(_) => MT::Timestamp,
+        MD::Dict(d) => MT::Dict(d.schema.clone()),
+        MD::List(l) => {
+            let element_hint = match hint {
+                MT::List(element_type) => element_type.as_ref(),
+                _ => hint,
+            };
+            let mut element_types: Vec<MT> = Vec::new();
+            for element in l {
+                let element_type = value_type(element, element_hint);
+                if !element_types.contains(&element_type) {
+                    element_types.push(element_type);
+                }
+            }
+            let element_type = match element_types.len() {
+                0 => element_hint.clone(),
+                1 => element_types.remove(0),
+                _ => MT::OneOf(element_types),
+            };
+            MT::List(Box::new(element_type))
+        }
+    }
+}
+
+/// Returns the value of an Int or a FixedInt.
+fn int_value(md: &MessageData) -> Option<i128> {
+    match md {
+        MD::Int(val) => Some((*val).into()),
+        MD::FixedInt(val) => Some(val.value),
+        _ => None,
+    }
+}
+
+/// Checks that the value fits into the destination integer type.
+fn checked_int(src: &MessageData, dst: &MT, value: i128) -> ConversionResult {
+    let out_of_range = |min: i128, max: i128| {
+        ConversionError::new(
+            src,
+            dst,
+            &format!("value {value} is out of range of {dst} ({min} to {max})"),
+        )
+    };
+    match dst {
+        MT::Int => match i64::try_from(value) {
+            Ok(val) => Ok(MD::Int(val)),
+            Err(_) => Err(out_of_range(i64::MIN.into(), i64::MAX.into())),
+        },
+        MT::FixedInt(int_type) => match FixedInt::new(value, *int_type) {
+            Some(val) => Ok(MD::FixedInt(val)),
+            None => Err(out_of_range(int_type.min(), int_type.max())),
+        },
+        _ => unreachable!("dst should be an integer type"),
+    }
 }
 
 fn int_to_text(src: &MessageData, _dst: &MT) -> ConversionResult {
-    if let MD::Int(val) = src {
+    if let Some(val) = int_value(src) {
         return Ok(MD::Text(Text {
             value: val.to_string(),
             content_type: TextContentType::Plain,
         }));
     }
-    unreachable!("src should be an Int");
+    unreachable!("src should be an Int or a FixedInt");
 }
 
 fn int_to_float(src: &MessageData, _dst: &MT) -> ConversionResult {
-    if let MD::Int(val) = src {
-        return Ok(MD::Float(*val as f32));
+    if let Some(val) = int_value(src) {
+        return Ok(MD::Float(val as f64));
+    }
+    unreachable!("src should be an Int or a FixedInt")
+}
+
+fn int_to_fixed_int(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let Some(val) = int_value(src) {
+        return checked_int(src, dst, val);
+    }
+    unreachable!("src should be an Int or a FixedInt")
+}
+
+fn fixed_int_to_int(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::FixedInt(val) = src {
+        return checked_int(src, dst, val.value);
+    }
+    unreachable!("src should be a FixedInt")
+}
+
+/// Converts a Float to a FixedInt, rounding towards zero.
+fn float_to_fixed_int(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Float(val) = src {
+        return checked_float_to_int(src, dst, val.trunc());
+    }
+    unreachable!("src should be a Float")
+}
+
+/// Checks that the float value, already rounded to an integer, fits into the destination integer
+/// type.
+fn checked_float_to_int(src: &MessageData, dst: &MT, value: f64) -> ConversionResult {
+    if !value.is_finite() {
+        return Err(ConversionError::new(
+            src,
+            dst,
+            "value is not a finite number",
+        ));
+    }
+    // Saturates for values beyond the range of i128, which are out of range anyway
+    checked_int(src, dst, value as i128)
+}
+
+fn text_to_fixed_int(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        return match text.value.parse::<i128>() {
+            Ok(res) => checked_int(src, dst, res),
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+        };
     }
-    unreachable!("src should be a Int")
+    unreachable!("src should be Text")
 }
 
 fn float_to_text(src: &MessageData, _dst: &MT) -> ConversionResult {
@@ -126,104 +850,877 @@ fn float_to_text(src: &MessageData, _dst: &MT) -> ConversionResult {
     unreachable!("src should be a Float");
 }
 
-fn float_to_int(src: &MessageData, _dst: &MT) -> ConversionResult {
+fn float_to_int(src: &MessageData, dst: &MT) -> ConversionResult {
     if let MD::Float(val) = src {
-        return Ok(MD::Int(*val as i64));
+        return checked_float_to_int(src, dst, val.trunc());
     }
     unreachable!("src should be a Float")
 }
 
-fn text_to_int(src: &MessageData, _dst: &MT) -> ConversionResult {
+fn text_to_int(src: &MessageData, dst: &MT) -> ConversionResult {
     if let MD::Text(text) = src {
         return match text.value.parse::<i64>() {
             Ok(res) => Ok(MD::Int(res)),
-            Err(_) => Err(ConversionError {}), // FIXME: pass the error
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
         };
     }
     unreachable!("src should be Text")
 }
 
-fn text_to_float(src: &MessageData, _dst: &MT) -> ConversionResult {
+fn text_to_float(src: &MessageData, dst: &MT) -> ConversionResult {
     if let MD::Text(text) = src {
-        return match text.value.parse::<f32>() {
+        return match text.value.parse::<f64>() {
             Ok(res) => Ok(MD::Float(res)),
-            Err(_) => Err(ConversionError {}), // FIXME: pass the error
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
         };
     }
     unreachable!("src should be Text")
 }
 
-fn dict_to_text_json(_src: &MessageData, _dst: &MT) -> ConversionResult {
-    unimplemented!();
+fn bool_to_text(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Bool(val) = src {
+        return Ok(MD::Text(Text {
+            value: val.to_string(),
+            content_type: TextContentType::Plain,
+        }));
+    }
+    unreachable!("src should be a Bool")
 }
 
-#[cfg(test)]
-mod test {
-    use crate::conversion;
-    use crate::message::MessageData as MD;
-    use crate::message::MessageType as MT;
-    use crate::message::*;
-    use crate::BinaryContentType::*;
-    use crate::TextContentType::*;
+fn bool_to_int(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Bool(val) = src {
+        return Ok(MD::Int(*val as i64));
+    }
+    unreachable!("src should be a Bool")
+}
 
-    fn assert_has_bidirectional_conversion(src: MT, dst: MT) {
-        assert!(conversion::find(&src, &dst).is_ok());
-        assert!(conversion::find(&dst, &src).is_ok());
+fn text_to_bool(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        return match text.value.parse::<bool>() {
+            Ok(res) => Ok(MD::Bool(res)),
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+        };
     }
+    unreachable!("src should be Text")
+}
 
-    fn assert_has_conversion(src: MT, dst: MT) {
-        assert!(conversion::find(&src, &dst).is_ok())
+fn int_to_bool(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Int(val) = src {
+        return match val {
+            0 => Ok(MD::Bool(false)),
+            1 => Ok(MD::Bool(true)),
+            _ => Err(ConversionError::new(
+                src,
+                dst,
+                "only 0 and 1 can be converted to boolean",
+            )),
+        };
     }
+    unreachable!("src should be an Int")
+}
 
-    fn assert_has_no_conversion(src: MT, dst: MT) {
-        assert!(conversion::find(&src, &dst).is_err())
+fn null_to_text_json(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Null = src {
+        return Ok(MD::Text(Text {
+            value: "null".to_string(),
+            content_type: TextContentType::Json,
+        }));
     }
+    unreachable!("src should be Null")
+}
 
-    #[test]
-    fn test_conversion_basic() {
-        assert_has_bidirectional_conversion(MT::Int, MT::Int);
-        assert_has_bidirectional_conversion(MT::Float, MT::Float);
-        assert_has_bidirectional_conversion(MT::Text(Plain), MT::Text(Plain));
-        assert_has_bidirectional_conversion(MT::Binary(Unknown), MT::Binary(Unknown));
+fn timestamp_to_text(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Timestamp(val) = src {
+        return Ok(MD::Text(Text {
+            value: val.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            content_type: TextContentType::Plain,
+        }));
+    }
+    unreachable!("src should be a Timestamp")
+}
 
-        let empty_schema = DictSchema::new();
+/// Converts a timestamp to the number of milliseconds since the Unix epoch.
+fn timestamp_to_int(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Timestamp(val) = src {
+        return Ok(MD::Int(val.timestamp_millis()));
+    }
+    unreachable!("src should be a Timestamp")
+}
 
-        assert_has_bidirectional_conversion(MT::Int, MT::Float);
-        assert_has_bidirectional_conversion(MT::Int, MT::Text(Plain));
-        assert_has_bidirectional_conversion(MT::Float, MT::Text(Plain));
-        assert_has_no_conversion(MT::Int, MT::Binary(Unknown));
-        assert_has_no_conversion(MT::Int, MT::Dict(empty_schema.clone()));
-        assert_has_no_conversion(MT::Float, MT::Binary(Unknown));
-        assert_has_no_conversion(MT::Float, MT::Dict(empty_schema.clone()));
-        assert_has_no_conversion(MT::Text(Plain), MT::Binary(Unknown));
-        assert_has_no_conversion(MT::Binary(Unknown), MT::Text(Plain));
+/// Parses an RFC 3339 timestamp, e.g. "2022-08-01T12:00:00.5+02:00".
+fn text_to_timestamp(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        return match DateTime::parse_from_rfc3339(&text.value) {
+            Ok(res) => Ok(MD::Timestamp(res.with_timezone(&Utc))),
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+        };
+    }
+    unreachable!("src should be Text")
+}
 
-        assert_has_conversion(MT::Dict(empty_schema), MT::Text(Json));
+/// Converts the number of milliseconds since the Unix epoch to a timestamp.
+fn int_to_timestamp(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Int(val) = src {
+        return match Utc.timestamp_millis_opt(*val).single() {
+            Some(res) => Ok(MD::Timestamp(res)),
+            None => Err(ConversionError::new(src, dst, "timestamp out of range")),
+        };
     }
+    unreachable!("src should be an Int")
+}
 
-    fn assert_conversion_result(src_type: MT, src: MD, dst_type: MT, dst_expected: MD) {
-        let conv = conversion::find(&src_type, &dst_type);
-        assert!(conv.is_ok());
-        let dst_actual = conv.unwrap()(&src, &dst_type);
-        assert!(dst_actual.is_ok());
-        assert_eq!(dst_actual.unwrap(), dst_expected);
+fn float_to_int_round(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Float(val) = src {
+        return checked_float_to_int(src, dst, val.round());
     }
+    unreachable!("src should be a Float")
+}
 
-    fn assert_conversion_error(src_type: MT, src: MD, dst_type: MT) {
-        let conv = conversion::find(&src_type, &dst_type);
-        assert!(conv.is_ok());
-        let dst_actual = conv.unwrap()(&src, &dst_type);
-        assert!(dst_actual.is_err());
+fn text_to_binary_utf8(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Text(text), MT::Binary(content_type)) = (src, dst) {
+        return Ok(MD::Binary(Binary {
+            value: text.value.as_bytes().to_vec(),
+            content_type: content_type.clone(),
+        }));
     }
+    unreachable!("src should be Text and dst should be a Binary type")
+}
 
-    fn make_md_text_plain(text: &str) -> MD {
-        MD::Text(Text {
-            value: text.to_string(),
-            content_type: Plain,
-        })
+fn binary_to_text_utf8(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Binary(binary), MT::Text(content_type)) = (src, dst) {
+        return match String::from_utf8(binary.value.clone()) {
+            Ok(value) => Ok(MD::Text(Text {
+                value,
+                content_type: content_type.clone(),
+            })),
+            Err(e) => Err(ConversionError::new(
+                src,
+                dst,
+                &format!("binary data is not valid UTF-8: {}", e.utf8_error()),
+            )),
+        };
     }
+    unreachable!("src should be Binary and dst should be a Text type")
+}
 
-    #[test]
+fn binary_to_text_base64(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Binary(binary), MT::Text(content_type)) = (src, dst) {
+        return Ok(MD::Text(Text {
+            value: base64::encode(&binary.value),
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("src should be Binary and dst should be a Text type")
+}
+
+fn text_base64_to_binary(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Text(text), MT::Binary(content_type)) = (src, dst) {
+        return match base64::decode(text.value.trim()) {
+            Ok(value) => Ok(MD::Binary(Binary {
+                value,
+                content_type: content_type.clone(),
+            })),
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+        };
+    }
+    unreachable!("src should be Text and dst should be a Binary type")
+}
+
+fn binary_to_text_hex(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Binary(binary), MT::Text(content_type)) = (src, dst) {
+        return Ok(MD::Text(Text {
+            value: binary.value.iter().map(|b| format!("{b:02x}")).collect(),
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("src should be Binary and dst should be a Text type")
+}
+
+fn text_hex_to_binary(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Text(text), MT::Binary(content_type)) = (src, dst) {
+        let hex = text.value.trim();
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(ConversionError::new(
+                src,
+                dst,
+                "hex string must have an even number of digits",
+            ));
+        }
+        let mut value = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            match u8::from_str_radix(&hex[i..i + 2], 16) {
+                Ok(b) => value.push(b),
+                Err(e) => return Err(ConversionError::new(src, dst, &e.to_string())),
+            }
+        }
+        return Ok(MD::Binary(Binary {
+            value,
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("src should be Text and dst should be a Binary type")
+}
+
+fn int_to_binary_le_i32(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Int(val), MT::Binary(content_type)) = (src, dst) {
+        return match i32::try_from(*val) {
+            Ok(val) => Ok(MD::Binary(Binary {
+                value: val.to_le_bytes().to_vec(),
+                content_type: content_type.clone(),
+            })),
+            Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+        };
+    }
+    unreachable!("src should be an Int and dst should be a Binary type")
+}
+
+fn binary_le_i32_to_int(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Binary(binary) = src {
+        return match <[u8; 4]>::try_from(binary.value.as_slice()) {
+            Ok(bytes) => Ok(MD::Int(i32::from_le_bytes(bytes) as i64)),
+            Err(_) => Err(ConversionError::new(
+                src,
+                dst,
+                &format!("expected 4 bytes, got {}", binary.value.len()),
+            )),
+        };
+    }
+    unreachable!("src should be Binary")
+}
+
+fn any_to_text_json(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MT::Text(content_type) = dst {
+        let value = message_data_to_json(src)?;
+        return Ok(MD::Text(Text {
+            value: value.to_string(),
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("dst should be a Text type")
+}
+
+fn dict_to_text_json(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::Dict(_) = src {
+        let value = message_data_to_json(src)?;
+        return Ok(MD::Text(Text {
+            value: value.to_string(),
+            content_type: TextContentType::Json,
+        }));
+    }
+    unreachable!("src should be a Dict")
+}
+
+fn list_to_text_json(src: &MessageData, _dst: &MT) -> ConversionResult {
+    if let MD::List(_) = src {
+        let value = message_data_to_json(src)?;
+        return Ok(MD::Text(Text {
+            value: value.to_string(),
+            content_type: TextContentType::Json,
+        }));
+    }
+    unreachable!("src should be a List")
+}
+
+fn text_json_to_data(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        return match serde_json::from_str::<Value>(&text.value) {
+            Ok(value) => json_to_message_data(&value, dst),
+            Err(e) => Err(ConversionError::new(
+                src,
+                dst,
+                &format!("invalid JSON: {e}"),
+            )),
+        };
+    }
+    unreachable!("src should be Text")
+}
+
+fn data_to_text_yaml(src: &MessageData, dst: &MT) -> ConversionResult {
+    let value = message_data_to_json(src)?;
+    match serde_yaml::to_string(&value) {
+        Ok(text) => Ok(MD::Text(Text {
+            value: text,
+            content_type: TextContentType::Yaml,
+        })),
+        Err(e) => Err(ConversionError::new(src, dst, &e.to_string())),
+    }
+}
+
+fn text_yaml_to_data(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        return match serde_yaml::from_str::<Value>(&text.value) {
+            Ok(value) => json_to_message_data(&value, dst),
+            Err(e) => Err(ConversionError::new(
+                src,
+                dst,
+                &format!("invalid YAML: {e}"),
+            )),
+        };
+    }
+    unreachable!("src should be Text")
+}
+
+fn data_to_binary_cbor(src: &MessageData, dst: &MT) -> ConversionResult {
+    match codec::data_to_cbor(src) {
+        Ok(bytes) => Ok(MD::Binary(Binary {
+            value: bytes,
+            content_type: BinaryContentType::Cbor,
+        })),
+        Err(e) => Err(ConversionError::new(src, dst, &e)),
+    }
+}
+
+fn binary_cbor_to_data(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let MD::Binary(binary) = src {
+        return match codec::cbor_to_json(&binary.value, max_depth) {
+            Ok(value) => json_to_message_data(&value, dst),
+            Err(e) => Err(ConversionError::new(src, dst, &e)),
+        };
+    }
+    unreachable!("src should be Binary")
+}
+
+/// Keeps the bytes of binary data, setting the content type of `dst`.
+fn relabel_binary(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Binary(binary), MT::Binary(content_type)) = (src, dst) {
+        return Ok(MD::Binary(Binary {
+            value: binary.value.clone(),
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("src and dst should be Binary")
+}
+
+/// Keeps the value of text, setting the content type of `dst`.
+fn relabel_text(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Text(text), MT::Text(content_type)) = (src, dst) {
+        return Ok(MD::Text(Text {
+            value: text.value.clone(),
+            content_type: content_type.clone(),
+        }));
+    }
+    unreachable!("src and dst should be Text")
+}
+
+/// Converts base64 text to hex and back through the decoded bytes, and JSON text to YAML and
+/// back through the parsed value.
+fn transcode_text(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let (MD::Text(text), MT::Text(content_type)) = (src, dst) {
+        let binary = MT::Binary(BinaryContentType::Unknown);
+        let value = match (&text.content_type, content_type) {
+            (TextContentType::Base64, TextContentType::Hex) => text_base64_to_binary(src, &binary)
+                .and_then(|bytes| binary_to_text_hex(&bytes, dst))
+                .map(|md| md.as_text().unwrap().to_string())
+                .map_err(|e| e.err_msg),
+            (TextContentType::Hex, TextContentType::Base64) => text_hex_to_binary(src, &binary)
+                .and_then(|bytes| binary_to_text_base64(&bytes, dst))
+                .map(|md| md.as_text().unwrap().to_string())
+                .map_err(|e| e.err_msg),
+            (TextContentType::Json, TextContentType::Yaml) => {
+                match serde_json::from_str::<Value>(&text.value) {
+                    Ok(value) => serde_yaml::to_string(&value).map_err(|e| e.to_string()),
+                    Err(e) => Err(format!("invalid JSON: {e}")),
+                }
+            }
+            (TextContentType::Yaml, TextContentType::Json) => {
+                match serde_yaml::from_str::<Value>(&text.value) {
+                    Ok(value) => Ok(value.to_string()),
+                    Err(e) => Err(format!("invalid YAML: {e}")),
+                }
+            }
+            _ => unreachable!("src and dst should be base64 and hex, or JSON and YAML text"),
+        };
+        return match value {
+            Ok(value) => Ok(MD::Text(Text {
+                value,
+                content_type: content_type.clone(),
+            })),
+            Err(e) => Err(ConversionError::new(src, dst, &e)),
+        };
+    }
+    unreachable!("src and dst should be Text")
+}
+
+/// Decodes CBOR or MessagePack binary data into a JSON value.
+fn encoded_binary_to_json(
+    src: &MessageData,
+    dst: &MT,
+    max_depth: usize,
+) -> Result<Value, ConversionError> {
+    if let MD::Binary(binary) = src {
+        let res = match binary.content_type {
+            BinaryContentType::Cbor => codec::cbor_to_json(&binary.value, max_depth),
+            BinaryContentType::MessagePack => codec::msgpack_to_json(&binary.value, max_depth),
+            _ => unreachable!("src should be CBOR or MessagePack data"),
+        };
+        return res.map_err(|e| ConversionError::new(src, dst, &e));
+    }
+    unreachable!("src should be Binary")
+}
+
+/// Encodes a value decoded from JSON or CBOR/MessagePack data into binary data of the content
+/// type of `dst`.
+fn any_to_encoded_binary(md: &MessageData, dst: &MT) -> ConversionResult {
+    match dst {
+        MT::Binary(BinaryContentType::Cbor) => data_to_binary_cbor(md, dst),
+        MT::Binary(BinaryContentType::MessagePack) => data_to_binary_msgpack(md, dst),
+        _ => unreachable!("dst should be CBOR or MessagePack binary"),
+    }
+}
+
+/// Converts CBOR data to MessagePack and back, through the decoded value.
+fn encoded_binary_to_binary(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    let value = encoded_binary_to_json(src, dst, max_depth)?;
+    any_to_encoded_binary(&json_to_any(&value), dst)
+}
+
+/// Writes the value held by CBOR or MessagePack data as JSON or YAML text.
+fn encoded_binary_to_text(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    let value = encoded_binary_to_json(src, dst, max_depth)?;
+    let (text, content_type) = match dst {
+        MT::Text(TextContentType::Json) => (value.to_string(), TextContentType::Json),
+        MT::Text(TextContentType::Yaml) => match serde_yaml::to_string(&value) {
+            Ok(text) => (text, TextContentType::Yaml),
+            Err(e) => return Err(ConversionError::new(src, dst, &e.to_string())),
+        },
+        _ => unreachable!("dst should be JSON or YAML text"),
+    };
+    Ok(MD::Text(Text {
+        value: text,
+        content_type,
+    }))
+}
+
+/// Encodes the value held by JSON or YAML text as CBOR or MessagePack data.
+fn text_to_encoded_binary(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::Text(text) = src {
+        let value = match text.content_type {
+            TextContentType::Yaml => text_yaml_to_data(src, &MT::Any),
+            _ => text_json_to_data(src, &MT::Any),
+        };
+        let value = value.map_err(|e| ConversionError::new(src, dst, &e.err_msg))?;
+        return any_to_encoded_binary(&value, dst);
+    }
+    unreachable!("src should be Text")
+}
+
+fn data_to_binary_msgpack(src: &MessageData, dst: &MT) -> ConversionResult {
+    match codec::data_to_msgpack(src) {
+        Ok(bytes) => Ok(MD::Binary(Binary {
+            value: bytes,
+            content_type: BinaryContentType::MessagePack,
+        })),
+        Err(e) => Err(ConversionError::new(src, dst, &e)),
+    }
+}
+
+fn binary_msgpack_to_data(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let MD::Binary(binary) = src {
+        return match codec::msgpack_to_json(&binary.value, max_depth) {
+            Ok(value) => json_to_message_data(&value, dst),
+            Err(e) => Err(ConversionError::new(src, dst, &e)),
+        };
+    }
+    unreachable!("src should be Binary")
+}
+
+/// Checks if the type can be a CSV field: a simple value, which may be missing.
+fn is_csv_field_type(mt: &MT) -> bool {
+    match mt {
+        MT::Text(_) | MT::Int | MT::FixedInt(_) | MT::Float | MT::Bool | MT::Null => true,
+        MT::Timestamp => true,
+        MT::Optional(inner) => is_csv_field_type(inner),
+        _ => false,
+    }
+}
+
+/// Checks if the type can be a CSV row: a dictionary with simple values.
+fn is_csv_row_type(mt: &MT) -> bool {
+    match mt {
+        MT::Dict(schema) => schema.values().all(is_csv_field_type),
+        _ => false,
+    }
+}
+
+/// Converts a list of dictionaries to CSV, with a column for each key. The header row holds the
+/// keys in sorted order.
+fn list_to_text_csv(src: &MessageData, dst: &MT) -> ConversionResult {
+    if let MD::List(rows) = src {
+        let mut keys: BTreeSet<&String> = BTreeSet::new();
+        for row in rows {
+            if let MD::Dict(d) = row {
+                keys.extend(d.schema.keys());
+            }
+        }
+
+        let csv_error = |e: csv::Error| ConversionError::new(src, dst, &e.to_string());
+        let mut writer = csv::Writer::from_writer(vec![]);
+        if !keys.is_empty() {
+            writer.write_record(&keys).map_err(csv_error)?;
+        }
+        for (index, row) in rows.iter().enumerate() {
+            let d = match row {
+                MD::Dict(d) => d,
+                _ => {
+                    return Err(ConversionError::new(row, dst, "row is not a dictionary")
+                        .in_key(&index.to_string()))
+                }
+            };
+            let mut record = Vec::with_capacity(keys.len());
+            for key in &keys {
+                let field = match d.data.get(*key) {
+                    None | Some(MD::Null) => String::new(),
+                    Some(MD::Text(t)) => t.value.clone(),
+                    Some(md @ (MD::Int(_) | MD::FixedInt(_) | MD::Float(_) | MD::Bool(_))) => {
+                        md.to_string()
+                    }
+                    Some(MD::Timestamp(t)) => t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                    Some(md) => {
+                        return Err(ConversionError::new(
+                            md,
+                            dst,
+                            "value can't be represented in CSV",
+                        )
+                        .in_key(key)
+                        .in_key(&index.to_string()))
+                    }
+                };
+                record.push(field);
+            }
+            writer.write_record(&record).map_err(csv_error)?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| ConversionError::new(src, dst, &e.to_string()))?;
+        return Ok(MD::Text(Text {
+            value: String::from_utf8(bytes).expect("CSV writer should produce UTF-8"),
+            content_type: TextContentType::Csv,
+        }));
+    }
+    unreachable!("src should be a List")
+}
+
+/// Converts CSV with a header row to a list of dictionaries. Columns are matched to the keys by
+/// name; empty fields are treated as missing values for Optional keys, and as null for Null keys.
+fn text_csv_to_list(src: &MessageData, dst: &MT, max_depth: usize) -> ConversionResult {
+    if let (MD::Text(text), MT::List(element_type)) = (src, dst) {
+        let schema = match element_type.as_ref() {
+            MT::Dict(schema) => schema,
+            _ => unreachable!("dst should be a list of dictionaries"),
+        };
+        let mut reader = csv::Reader::from_reader(text.value.as_bytes());
+        let csv_error =
+            |e: csv::Error| ConversionError::new(src, dst, &format!("invalid CSV: {e}"));
+        let headers = reader.headers().map_err(csv_error)?.clone();
+        let mut rows = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.map_err(csv_error)?;
+            let mut data = HashMap::new();
+            for (key, key_type) in schema {
+                let field = headers
+                    .iter()
+                    .position(|h| h == key)
+                    .and_then(|i| record.get(i));
+                let (field, field_type) = match (field, key_type) {
+                    (None | Some(""), MT::Optional(_)) => continue,
+                    (Some(field), MT::Optional(inner)) => (field, inner.as_ref()),
+                    (Some(field), _) => (field, key_type),
+                    (None, _) => {
+                        return Err(ConversionError::new(src, key_type, "column is missing")
+                            .in_key(key)
+                            .in_key(&index.to_string()))
+                    }
+                };
+                let value = csv_field_to_data(field, field_type, max_depth)
+                    .map_err(|e| e.in_key(key).in_key(&index.to_string()))?;
+                data.insert(key.clone(), value);
+            }
+            rows.push(MD::Dict(Dict {
+                data,
+                schema: schema.clone(),
+            }));
+        }
+        return Ok(MD::List(rows));
+    }
+    unreachable!("src should be Text and dst should be a List type")
+}
+
+fn csv_field_to_data(field: &str, mt: &MT, max_depth: usize) -> ConversionResult {
+    let text = |content_type: TextContentType| {
+        MD::Text(Text {
+            value: field.to_string(),
+            content_type,
+        })
+    };
+    match mt {
+        MT::Text(content_type) => Ok(text(content_type.clone())),
+        MT::Null if field.is_empty() => Ok(MD::Null),
+        _ => {
+            let src = text(TextContentType::Plain);
+            let conv = find_builtin(&MT::Text(TextContentType::Plain), mt, max_depth)
+                .map_err(|e| ConversionError::new(&src, mt, &e.to_string()))?;
+            conv(&src, mt)
+        }
+    }
+}
+
+/// Maps MessageData to a JSON value.
+///
+/// Dictionaries become JSON objects, Lists become JSON arrays, Binary values are encoded as base64
+/// strings and Timestamps as RFC 3339 strings.
+pub(crate) fn message_data_to_json(md: &MessageData) -> Result<Value, ConversionError> {
+    match md {
+        MD::Text(t) => Ok(Value::String(t.value.clone())),
+        MD::Binary(b) => Ok(Value::String(base64::encode(&b.value))),
+        MD::Int(i) => Ok(Value::Number(Number::from(*i))),
+        MD::FixedInt(i) => match (i64::try_from(i.value), u64::try_from(i.value)) {
+            (Ok(val), _) => Ok(Value::Number(Number::from(val))),
+            (_, Ok(val)) => Ok(Value::Number(Number::from(val))),
+            _ => unreachable!("FixedInt value should fit into i64 or u64"),
+        },
+        MD::Bool(b) => Ok(Value::Bool(*b)),
+        MD::Null => Ok(Value::Null),
+        MD::Timestamp(t) => Ok(Value::String(
+            t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        )),
+        MD::Float(f) => match Number::from_f64(*f) {
+            Some(n) => Ok(Value::Number(n)),
+            None => Err(ConversionError::new(
+                md,
+                &MT::Text(TextContentType::Json),
+                "value can't be represented in JSON",
+            )),
+        },
+        MD::Dict(d) => {
+            let mut obj = Map::new();
+            for (key, value) in &d.data {
+                let json_value = message_data_to_json(value).map_err(|e| e.in_key(key))?;
+                obj.insert(key.clone(), json_value);
+            }
+            Ok(Value::Object(obj))
+        }
+        MD::List(l) => {
+            let mut arr = Vec::with_capacity(l.len());
+            for (index, value) in l.iter().enumerate() {
+                arr.push(message_data_to_json(value).map_err(|e| e.in_key(&index.to_string()))?);
+            }
+            Ok(Value::Array(arr))
+        }
+    }
+}
+
+/// Maps a JSON value to MessageData of the given type.
+///
+/// On failure, the `src` of the error is the offending JSON (sub-)value, and the key path points
+/// to it from the top-level object.
+pub(crate) fn json_to_message_data(value: &Value, mt: &MT) -> ConversionResult {
+    let mismatch = || {
+        let src = MD::Text(Text {
+            value: value.to_string(),
+            content_type: TextContentType::Json,
+        });
+        ConversionError::new(&src, mt, "JSON value has a different type")
+    };
+    match mt {
+        MT::Text(content_type) => match value {
+            Value::String(s) => Ok(MD::Text(Text {
+                value: s.clone(),
+                content_type: content_type.clone(),
+            })),
+            _ => Err(mismatch()),
+        },
+        MT::Binary(content_type) => match value.as_str().map(base64::decode) {
+            Some(Ok(bytes)) => Ok(MD::Binary(Binary {
+                value: bytes,
+                content_type: content_type.clone(),
+            })),
+            _ => Err(mismatch()),
+        },
+        MT::Int => value.as_i64().map(MD::Int).ok_or_else(mismatch),
+        MT::FixedInt(int_type) => {
+            let int = value
+                .as_i64()
+                .map(i128::from)
+                .or_else(|| value.as_u64().map(i128::from))
+                .ok_or_else(mismatch)?;
+            FixedInt::new(int, *int_type)
+                .map(MD::FixedInt)
+                .ok_or_else(|| {
+                    let mut err = mismatch();
+                    err.err_msg = format!(
+                        "value {int} is out of range of {mt} ({} to {})",
+                        int_type.min(),
+                        int_type.max()
+                    );
+                    err
+                })
+        }
+        MT::Float => value.as_f64().map(MD::Float).ok_or_else(mismatch),
+        MT::Bool => value.as_bool().map(MD::Bool).ok_or_else(mismatch),
+        MT::Null => value.as_null().map(|_| MD::Null).ok_or_else(mismatch),
+        MT::Timestamp => match value.as_str().map(DateTime::parse_from_rfc3339) {
+            Some(Ok(t)) => Ok(MD::Timestamp(t.with_timezone(&Utc))),
+            _ => Err(mismatch()),
+        },
+        MT::Dict(schema) => {
+            let obj = value.as_object().ok_or_else(mismatch)?;
+            let mut data = HashMap::new();
+            for (key, key_type) in schema {
+                let key_value = match obj.get(key) {
+                    Some(key_value) => key_value,
+                    None if matches!(key_type, MT::Optional(_)) => continue,
+                    None => {
+                        let mut err = mismatch().in_key(key);
+                        err.dst_type = Box::new(key_type.clone());
+                        err.err_msg = "key is missing".to_string();
+                        return Err(err);
+                    }
+                };
+                data.insert(
+                    key.clone(),
+                    json_to_message_data(key_value, key_type).map_err(|e| e.in_key(key))?,
+                );
+            }
+            Ok(MD::Dict(Dict {
+                data,
+                schema: schema.clone(),
+            }))
+        }
+        MT::List(element_type) => {
+            let arr = value.as_array().ok_or_else(mismatch)?;
+            let mut res = Vec::with_capacity(arr.len());
+            for (index, element) in arr.iter().enumerate() {
+                res.push(
+                    json_to_message_data(element, element_type)
+                        .map_err(|e| e.in_key(&index.to_string()))?,
+                );
+            }
+            Ok(MD::List(res))
+        }
+        MT::Any => Ok(json_to_any(value)),
+        MT::Optional(inner) => match value {
+            Value::Null => Ok(MD::Null),
+            _ => json_to_message_data(value, inner),
+        },
+        MT::OneOf(members) => members
+            .iter()
+            .find_map(|member| json_to_message_data(value, member).ok())
+            .ok_or_else(mismatch),
+    }
+}
+
+/// Maps a JSON value to MessageData of the type which fits the value best.
+fn json_to_any(value: &Value) -> MessageData {
+    match value {
+        Value::Null => MD::Null,
+        Value::Bool(b) => MD::Bool(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => MD::Int(i),
+            (None, Some(u)) => MD::FixedInt(FixedInt {
+                value: u.into(),
+                int_type: IntType::U64,
+            }),
+            _ => MD::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => MD::Text(Text {
+            value: s.clone(),
+            content_type: TextContentType::Plain,
+        }),
+        Value::Array(arr) => MD::List(arr.iter().map(json_to_any).collect()),
+        Value::Object(obj) => {
+            let data: HashMap<String, MessageData> = obj
+                .iter()
+                .map(|(key, value)| (key.clone(), json_to_any(value)))
+                .collect();
+            let schema = data
+                .iter()
+                .map(|(key, md)| (key.clone(), value_type(md, &MT::Any)))
+                .collect();
+            MD::Dict(Dict { data, schema })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::conversion;
+    use crate::message::MessageData as MD;
+    use crate::message::MessageType as MT;
+    use crate::message::*;
+    use crate::BinaryContentType::*;
+    use crate::TextContentType::*;
+
+    fn assert_has_bidirectional_conversion(src: MT, dst: MT) {
+        assert!(conversion::find(&src, &dst, DEFAULT_MAX_NESTING_DEPTH).is_ok());
+        assert!(conversion::find(&dst, &src, DEFAULT_MAX_NESTING_DEPTH).is_ok());
+    }
+
+    fn assert_has_conversion(src: MT, dst: MT) {
+        assert!(conversion::find(&src, &dst, DEFAULT_MAX_NESTING_DEPTH).is_ok())
+    }
+
+    fn assert_has_no_conversion(src: MT, dst: MT) {
+        assert!(conversion::find(&src, &dst, DEFAULT_MAX_NESTING_DEPTH).is_err())
+    }
+
+    #[test]
+    fn test_conversion_basic() {
+        assert_has_bidirectional_conversion(MT::Int, MT::Int);
+        assert_has_bidirectional_conversion(MT::Float, MT::Float);
+        assert_has_bidirectional_conversion(MT::Text(Plain), MT::Text(Plain));
+        assert_has_bidirectional_conversion(MT::Binary(Unknown), MT::Binary(Unknown));
+
+        let empty_schema = DictSchema::new();
+
+        assert_has_bidirectional_conversion(MT::Int, MT::Float);
+        assert_has_bidirectional_conversion(MT::Int, MT::Text(Plain));
+        assert_has_bidirectional_conversion(MT::Float, MT::Text(Plain));
+        assert_has_no_conversion(MT::Int, MT::Binary(Unknown));
+        assert_has_no_conversion(MT::Int, MT::Dict(empty_schema.clone()));
+        assert_has_no_conversion(MT::Float, MT::Binary(Unknown));
+        assert_has_no_conversion(MT::Float, MT::Dict(empty_schema.clone()));
+        assert_has_bidirectional_conversion(MT::Text(Plain), MT::Binary(Unknown));
+        assert_has_no_conversion(MT::Binary(Unknown), MT::Text(Json));
+        // Simple values aren't encoded implicitly
+        for mt in [MT::Int, MT::Bool, MT::Float, MT::Timestamp] {
+            for content_type in [Base64, Hex, Csv, Xml] {
+                assert_has_no_conversion(mt.clone(), MT::Text(content_type));
+            }
+            assert_has_conversion(mt, MT::Text(Json));
+        }
+
+        assert_has_conversion(MT::Dict(empty_schema.clone()), MT::Text(Json));
+        assert_has_conversion(MT::Text(Json), MT::Dict(empty_schema.clone()));
+        // via Text(Json)
+        assert_has_conversion(MT::Dict(empty_schema.clone()), MT::Text(Plain));
+        assert_has_no_conversion(MT::Text(Plain), MT::Dict(empty_schema));
+    }
+
+    fn assert_conversion_result(src_type: MT, src: MD, dst_type: MT, dst_expected: MD) {
+        let conv = conversion::find(&src_type, &dst_type, DEFAULT_MAX_NESTING_DEPTH);
+        assert!(conv.is_ok());
+        let dst_actual = conv.unwrap()(&src, &dst_type);
+        assert!(dst_actual.is_ok());
+        assert_eq!(dst_actual.unwrap(), dst_expected);
+    }
+
+    fn assert_conversion_error(src_type: MT, src: MD, dst_type: MT) {
+        let conv = conversion::find(&src_type, &dst_type, DEFAULT_MAX_NESTING_DEPTH);
+        assert!(conv.is_ok());
+        let dst_actual = conv.unwrap()(&src, &dst_type);
+        assert!(dst_actual.is_err());
+    }
+
+    fn make_md_text_plain(text: &str) -> MD {
+        MD::Text(Text {
+            value: text.to_string(),
+            content_type: Plain,
+        })
+    }
+
+    #[test]
     fn test_conversion_result() {
         assert_conversion_result(
             MT::Int,
@@ -233,6 +1730,21 @@ mod test {
         );
 
         assert_conversion_error(MT::Text(Plain), make_md_text_plain("aaa"), MT::Int);
+
+        // Written as JSON, with the JSON content type
+        assert_conversion_result(
+            MT::Float,
+            MD::Float(2.5),
+            MT::Text(Json),
+            make_md_text("2.5", Json),
+        );
+        assert_conversion_result(
+            MT::Timestamp,
+            MD::Timestamp(chrono::DateTime::from_timestamp(0, 0).unwrap()),
+            MT::Text(Json),
+            make_md_text(r#""1970-01-01T00:00:00Z""#, Json),
+        );
+        assert_conversion_error(MT::Float, MD::Float(f64::NAN), MT::Text(Json));
     }
 
     #[test]
@@ -256,15 +1768,1508 @@ mod test {
         assert_has_bidirectional_conversion(MT::Dict(schema_src), MT::Dict(schema_dst));
     }
 
+    fn make_md_dict(data: Vec<(&str, MD, MT)>) -> MD {
+        MD::Dict(Dict {
+            data: data
+                .iter()
+                .map(|(k, v, _)| (k.to_string(), v.clone()))
+                .collect(),
+            schema: data
+                .iter()
+                .map(|(k, _, t)| (k.to_string(), t.clone()))
+                .collect(),
+        })
+    }
+
+    fn md_dict_schema(md: &MD) -> MT {
+        if let MD::Dict(dict) = md {
+            return MT::Dict(dict.schema.clone());
+        }
+        unreachable!("md should be a Dict")
+    }
+
     #[test]
-    fn test_conversion_dict_nested() {
-        let schema_nested_src = DictSchema::from([("nested_key".to_string(), MT::Int)]);
+    fn test_conversion_dict_simple_result() {
+        let src = make_md_dict(vec![("key", MD::Int(42), MT::Int)]);
+        let dst = make_md_dict(vec![("key", MD::Int(42), MT::Int)]);
+        assert_conversion_result(md_dict_schema(&src), src, md_dict_schema(&dst), dst);
+    }
 
-        let schema_nested_dst = DictSchema::from([("nested_key".to_string(), MT::Float)]);
+    #[test]
+    fn test_conversion_dict_implicit_result() {
+        let src = make_md_dict(vec![("key", MD::Int(42), MT::Int)]);
+        let dst = make_md_dict(vec![("key", MD::Float(42.0), MT::Float)]);
+        assert_conversion_result(md_dict_schema(&src), src, md_dict_schema(&dst), dst);
+    }
 
-        let schema_src = DictSchema::from([("nested".to_string(), MT::Dict(schema_nested_src))]);
-        let schema_dst = DictSchema::from([("nested".to_string(), MT::Dict(schema_nested_dst))]);
+    #[test]
+    fn test_conversion_dict_dropped_keys_result() {
+        let src = make_md_dict(vec![
+            ("key1", MD::Int(1), MT::Int),
+            ("key2", make_md_text_plain("2"), MT::Text(Plain)),
+        ]);
+        let dst = make_md_dict(vec![("key2", MD::Int(2), MT::Int)]);
+        assert_conversion_result(md_dict_schema(&src), src, md_dict_schema(&dst), dst);
+    }
 
-        assert_has_bidirectional_conversion(MT::Dict(schema_src), MT::Dict(schema_dst));
+    #[test]
+    fn test_conversion_dict_value_error() {
+        let src = make_md_dict(vec![("key", make_md_text_plain("aaa"), MT::Text(Plain))]);
+        let dst_type = MT::Dict(DictSchema::from([("key".to_string(), MT::Int)]));
+        assert_conversion_error(md_dict_schema(&src), src, dst_type);
+    }
+
+    #[test]
+    fn test_conversion_dict_missing_key_error() {
+        let dst_type = MT::Dict(DictSchema::from([("key".to_string(), MT::Int)]));
+        let src = MD::Dict(Dict {
+            data: HashMap::new(),
+            schema: DictSchema::from([("key".to_string(), MT::Int)]),
+        });
+        let err = conversion::dict_to_dict(&src, &dst_type, DEFAULT_MAX_NESTING_DEPTH).unwrap_err();
+        assert_eq!(err.err_msg, "key is missing in the source dictionary");
+        assert_eq!(err.key_path.as_deref(), Some("key"));
+
+        // The data has the key, but the schema doesn't
+        let src = MD::Dict(Dict {
+            data: HashMap::from([("key".to_string(), MD::Int(1))]),
+            schema: DictSchema::new(),
+        });
+        let err = conversion::dict_to_dict(&src, &dst_type, DEFAULT_MAX_NESTING_DEPTH).unwrap_err();
+        assert_eq!(
+            err.err_msg,
+            "the schema of the source dictionary has no entry for key 'key'"
+        );
+        assert_eq!(err.key_path.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn test_conversion_dict_nested_result() {
+        let nested_src = make_md_dict(vec![("nested_key", MD::Int(42), MT::Int)]);
+        let nested_dst = make_md_dict(vec![("nested_key", MD::Float(42.0), MT::Float)]);
+        let src = make_md_dict(vec![(
+            "nested",
+            nested_src.clone(),
+            md_dict_schema(&nested_src),
+        )]);
+        let dst = make_md_dict(vec![(
+            "nested",
+            nested_dst.clone(),
+            md_dict_schema(&nested_dst),
+        )]);
+        assert_conversion_result(md_dict_schema(&src), src, md_dict_schema(&dst), dst);
+    }
+
+    fn make_md_text_json(text: &str) -> MD {
+        MD::Text(Text {
+            value: text.to_string(),
+            content_type: Json,
+        })
+    }
+
+    fn make_md_dict_all_types() -> MD {
+        let nested = make_md_dict(vec![("nested_key", MD::Int(-1), MT::Int)]);
+        make_md_dict(vec![
+            ("int", MD::Int(42), MT::Int),
+            ("float", MD::Float(0.5), MT::Float),
+            (
+                "text",
+                make_md_text_plain("hello \"world\""),
+                MT::Text(Plain),
+            ),
+            (
+                "binary",
+                MD::Binary(Binary {
+                    value: vec![0, 1, 2, 255],
+                    content_type: Unknown,
+                }),
+                MT::Binary(Unknown),
+            ),
+            ("nested", nested.clone(), md_dict_schema(&nested)),
+        ])
+    }
+
+    #[test]
+    fn test_conversion_dict_to_json_result() {
+        let src = make_md_dict_all_types();
+        assert_conversion_result(
+            md_dict_schema(&src),
+            src,
+            MT::Text(Json),
+            make_md_text_json(
+                r#"{"binary":"AAEC/w==","float":0.5,"int":42,"nested":{"nested_key":-1},"text":"hello \"world\""}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_conversion_json_to_dict_result() {
+        let dst = make_md_dict_all_types();
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text_json(
+                r#"{"int": 42, "float": 0.5, "text": "hello \"world\"", "binary": "AAEC/w==",
+                    "nested": {"nested_key": -1}, "extra": [1, 2, 3]}"#,
+            ),
+            md_dict_schema(&dst),
+            dst,
+        );
+    }
+
+    #[test]
+    fn test_conversion_dict_json_round_trip() {
+        let src = make_md_dict_all_types();
+        let schema = md_dict_schema(&src);
+        let to_json =
+            conversion::find(&schema, &MT::Text(Json), DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let from_json =
+            conversion::find(&MT::Text(Json), &schema, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let json = to_json(&src, &MT::Text(Json)).unwrap();
+        assert_eq!(from_json(&json, &schema).unwrap(), src);
+    }
+
+    #[test]
+    fn test_conversion_json_to_dict_error() {
+        let schema = MT::Dict(DictSchema::from([(
+            "nested".to_string(),
+            MT::Dict(DictSchema::from([("key".to_string(), MT::Int)])),
+        )]));
+        assert_conversion_error(
+            MT::Text(Json),
+            make_md_text_json("not json"),
+            schema.clone(),
+        );
+        assert_conversion_error(MT::Text(Json), make_md_text_json("[1, 2]"), schema.clone());
+        assert_conversion_error(
+            MT::Text(Json),
+            make_md_text_json(r#"{"nested": {}}"#),
+            schema.clone(),
+        );
+        assert_conversion_error(
+            MT::Text(Json),
+            make_md_text_json(r#"{"nested": {"key": "1"}}"#),
+            schema,
+        );
+    }
+
+    #[test]
+    fn test_conversion_json_key_path() {
+        let schema = MT::Dict(DictSchema::from([(
+            "nested".to_string(),
+            MT::Dict(DictSchema::from([("key".to_string(), MT::Int)])),
+        )]));
+        let conv = conversion::find(&MT::Text(Json), &schema, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+
+        let err = conv(&make_md_text_json(r#"{"nested": {"key": 1.5}}"#), &schema).unwrap_err();
+        assert_eq!(*err.src, make_md_text_json("1.5"));
+        assert_eq!(*err.dst_type, MT::Int);
+        assert_eq!(err.key_path, Some("nested.key".to_string()));
+
+        let err = conv(&make_md_text_json(r#"{"nested": {}}"#), &schema).unwrap_err();
+        assert_eq!(err.key_path, Some("nested.key".to_string()));
+        assert_eq!(err.err_msg, "key is missing");
+    }
+
+    #[test]
+    fn test_conversion_error_details() {
+        let src = make_md_text_plain("aaa");
+        let err = conversion::text_to_int(&src, &MT::Int).unwrap_err();
+        assert_eq!(*err.src, src);
+        assert_eq!(*err.dst_type, MT::Int);
+        assert_eq!(err.key_path, None);
+        assert_eq!(
+            err.to_string(),
+            "Failed to convert \"aaa\" to integer: invalid digit found in string"
+        );
+
+        let src = make_md_dict(vec![(
+            "nested",
+            make_md_dict(vec![("key", make_md_text_plain("aaa"), MT::Text(Plain))]),
+            MT::Dict(DictSchema::from([("key".to_string(), MT::Text(Plain))])),
+        )]);
+        let dst_type = MT::Dict(DictSchema::from([(
+            "nested".to_string(),
+            MT::Dict(DictSchema::from([("key".to_string(), MT::Float)])),
+        )]));
+        let err = conversion::find(&md_dict_schema(&src), &dst_type, DEFAULT_MAX_NESTING_DEPTH)
+            .unwrap()(&src, &dst_type)
+        .unwrap_err();
+        assert_eq!(err.key_path, Some("nested.key".to_string()));
+        assert_eq!(
+            err.to_string(),
+            "Failed to convert \"aaa\" to float (key 'nested.key'): invalid float literal"
+        );
+    }
+
+    fn list_of(element_type: MT) -> MT {
+        MT::List(Box::new(element_type))
+    }
+
+    #[test]
+    fn test_conversion_list() {
+        assert_has_bidirectional_conversion(list_of(MT::Int), list_of(MT::Float));
+        assert_has_bidirectional_conversion(list_of(MT::Int), MT::Text(Json));
+        assert_has_bidirectional_conversion(
+            list_of(list_of(MT::Int)),
+            list_of(list_of(MT::Text(Plain))),
+        );
+        assert_has_no_conversion(list_of(MT::Int), list_of(MT::Binary(Unknown)));
+        assert_has_conversion(list_of(MT::Int), MT::Text(Plain));
+        assert_has_no_conversion(list_of(MT::Int), MT::Int);
+        assert_has_no_conversion(MT::Int, list_of(MT::Int));
+        assert_has_no_conversion(list_of(MT::Int), list_of(list_of(MT::Int)));
+
+        let schema_src = DictSchema::from([("key".to_string(), list_of(MT::Int))]);
+        let schema_dst = DictSchema::from([("key".to_string(), list_of(MT::Float))]);
+        assert_has_bidirectional_conversion(MT::Dict(schema_src), MT::Dict(schema_dst));
+
+        assert_eq!(list_of(list_of(MT::Int)).to_string(), "list<list<integer>>");
+    }
+
+    #[test]
+    fn test_conversion_list_result() {
+        assert_conversion_result(
+            list_of(MT::Int),
+            MD::List(vec![MD::Int(1), MD::Int(2)]),
+            list_of(MT::Float),
+            MD::List(vec![MD::Float(1.0), MD::Float(2.0)]),
+        );
+        assert_conversion_result(
+            list_of(list_of(MT::Int)),
+            MD::List(vec![MD::List(vec![]), MD::List(vec![MD::Int(3)])]),
+            list_of(list_of(MT::Text(Plain))),
+            MD::List(vec![
+                MD::List(vec![]),
+                MD::List(vec![make_md_text_plain("3")]),
+            ]),
+        );
+        assert_conversion_result(
+            list_of(MT::Int),
+            MD::List(vec![MD::Int(1), MD::Int(2)]),
+            MT::Text(Json),
+            make_md_text_json("[1,2]"),
+        );
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text_json("[1.5, 2]"),
+            list_of(MT::Float),
+            MD::List(vec![MD::Float(1.5), MD::Float(2.0)]),
+        );
+
+        let src = make_md_dict(vec![(
+            "readings",
+            MD::List(vec![MD::Int(1), MD::Int(2)]),
+            list_of(MT::Int),
+        )]);
+        let dst = make_md_dict(vec![(
+            "readings",
+            MD::List(vec![MD::Float(1.0), MD::Float(2.0)]),
+            list_of(MT::Float),
+        )]);
+        assert_conversion_result(md_dict_schema(&src), src.clone(), md_dict_schema(&dst), dst);
+        assert_conversion_result(
+            md_dict_schema(&src),
+            src,
+            MT::Text(Json),
+            make_md_text_json(r#"{"readings":[1,2]}"#),
+        );
+    }
+
+    #[test]
+    fn test_conversion_list_error() {
+        let src = MD::List(vec![make_md_text_plain("1"), make_md_text_plain("aaa")]);
+        let dst_type = list_of(MT::Int);
+        let conv = conversion::find(
+            &list_of(MT::Text(Plain)),
+            &dst_type,
+            DEFAULT_MAX_NESTING_DEPTH,
+        )
+        .unwrap();
+        let err = conv(&src, &dst_type).unwrap_err();
+        assert_eq!(*err.src, make_md_text_plain("aaa"));
+        assert_eq!(err.key_path, Some("1".to_string()));
+
+        let conv = conversion::find(&MT::Text(Json), &dst_type, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let err = conv(&make_md_text_json("[1, 2, \"3\"]"), &dst_type).unwrap_err();
+        assert_eq!(err.key_path, Some("2".to_string()));
+    }
+
+    fn make_md_timestamp(text: &str) -> MD {
+        MD::Timestamp(
+            chrono::DateTime::parse_from_rfc3339(text)
+                .unwrap()
+                .with_timezone(&chrono::Utc),
+        )
+    }
+
+    #[test]
+    fn test_conversion_bool_null_timestamp() {
+        assert_has_bidirectional_conversion(MT::Bool, MT::Bool);
+        assert_has_bidirectional_conversion(MT::Null, MT::Null);
+        assert_has_bidirectional_conversion(MT::Timestamp, MT::Timestamp);
+
+        assert_has_bidirectional_conversion(MT::Bool, MT::Int);
+        assert_has_bidirectional_conversion(MT::Bool, MT::Text(Plain));
+        assert_has_bidirectional_conversion(MT::Timestamp, MT::Int);
+        assert_has_bidirectional_conversion(MT::Timestamp, MT::Text(Plain));
+        assert_has_bidirectional_conversion(MT::Null, MT::Text(Json));
+
+        // These must stay explicit
+        assert_has_no_conversion(MT::Float, MT::Bool);
+        assert_has_no_conversion(MT::Bool, MT::Float);
+        assert_has_no_conversion(MT::Bool, MT::Timestamp);
+        assert_has_no_conversion(MT::Timestamp, MT::Bool);
+        assert_has_no_conversion(MT::Timestamp, MT::Float);
+        assert_has_no_conversion(MT::Float, MT::Timestamp);
+        assert_has_no_conversion(MT::Bool, MT::Binary(Unknown));
+        assert_has_no_conversion(MT::Null, MT::Int);
+        assert_has_no_conversion(MT::Int, MT::Null);
+        assert_has_no_conversion(MT::Bool, MT::Null);
+        assert_has_no_conversion(MT::Null, MT::Bool);
+        assert_has_conversion(MT::Null, MT::Text(Plain));
+        assert_has_no_conversion(MT::Text(Plain), MT::Null);
+    }
+
+    #[test]
+    fn test_conversion_loss() {
+        assert!(
+            conversion::find_loss(&MT::Int, &MT::Text(Plain), DEFAULT_MAX_NESTING_DEPTH).is_none()
+        );
+        assert!(
+            conversion::find_loss(&MT::Text(Plain), &MT::Int, DEFAULT_MAX_NESTING_DEPTH).is_none()
+        );
+        assert!(conversion::find_loss(&MT::Float, &MT::Int, DEFAULT_MAX_NESTING_DEPTH).is_some());
+        assert!(conversion::find_loss(&MT::Int, &MT::Float, DEFAULT_MAX_NESTING_DEPTH).is_some());
+        assert!(
+            conversion::find_loss(&MT::Timestamp, &MT::Int, DEFAULT_MAX_NESTING_DEPTH).is_some()
+        );
+        assert!(conversion::find_loss(
+            &list_of(MT::Float),
+            &list_of(MT::Int),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_some());
+
+        let schema_src = DictSchema::from([
+            ("a".to_string(), MT::Int),
+            ("b".to_string(), MT::Float),
+            ("c".to_string(), MT::Bool),
+        ]);
+        let schema_dst = DictSchema::from([("a".to_string(), MT::Text(Plain))]);
+        assert_eq!(
+            conversion::find_loss(
+                &MT::Dict(schema_src.clone()),
+                &MT::Dict(schema_dst),
+                DEFAULT_MAX_NESTING_DEPTH
+            ),
+            Some(r#"keys ["b", "c"] are dropped"#.to_string())
+        );
+        let schema_dst = DictSchema::from([
+            ("a".to_string(), MT::Int),
+            ("b".to_string(), MT::Int),
+            ("c".to_string(), MT::Bool),
+        ]);
+        assert_eq!(
+            conversion::find_loss(
+                &MT::Dict(schema_src.clone()),
+                &MT::Dict(schema_dst),
+                DEFAULT_MAX_NESTING_DEPTH
+            ),
+            Some("key 'b': the fractional part is truncated".to_string())
+        );
+        assert!(conversion::find_loss(
+            &MT::Dict(schema_src.clone()),
+            &MT::Dict(schema_src),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_conversion_loss_decoded_dict() {
+        let loss = |src: MT, dst: MT| conversion::find_loss(&src, &dst, DEFAULT_MAX_NESTING_DEPTH);
+        let dict = MT::Dict(DictSchema::from([("a".to_string(), MT::Int)]));
+        let expected = Some("keys missing from the dictionary schema are dropped".to_string());
+        for src in [
+            MT::Text(Json),
+            MT::Text(Yaml),
+            MT::Binary(Cbor),
+            MT::Binary(MessagePack),
+        ] {
+            assert_eq!(loss(src.clone(), dict.clone()), expected);
+            assert_eq!(loss(src.clone(), optional(dict.clone())), expected);
+            assert_eq!(loss(src.clone(), list_of(dict.clone())), expected);
+            assert!(loss(src.clone(), list_of(MT::Int)).is_none());
+            assert!(loss(src, MT::Any).is_none());
+        }
+
+        // The key "b" is dropped by the conversion
+        let conv = conversion::find(&MT::Text(Json), &dict, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let res = conv(&make_md_text_json(r#"{"a": 1, "b": 2}"#), &dict).unwrap();
+        assert!(matches!(res, MD::Dict(dict) if dict.data.keys().eq(["a"].iter())));
+    }
+
+    #[test]
+    fn test_conversion_loss_nested() {
+        let loss = |src: MT, dst: MT| conversion::find_loss(&src, &dst, DEFAULT_MAX_NESTING_DEPTH);
+        assert_eq!(
+            loss(optional(MT::Float), optional(MT::Int)),
+            Some("float values: the fractional part is truncated".to_string())
+        );
+        assert!(loss(optional(MT::Int), optional(MT::Int)).is_none());
+        assert!(loss(MT::Null, optional(MT::Int)).is_none());
+        let dict = |mt: MT| MT::Dict(DictSchema::from([("k".to_string(), mt)]));
+        assert_eq!(
+            loss(dict(optional(MT::Float)), dict(optional(MT::Int))),
+            Some("key 'k': float values: the fractional part is truncated".to_string())
+        );
+        assert!(loss(
+            list_of(dict(optional(MT::Float))),
+            list_of(dict(optional(MT::Int)))
+        )
+        .is_some());
+
+        // Lossy unless a member can hold the value without loss
+        assert!(loss(MT::Float, MT::OneOf(vec![MT::Int, MT::Bool])).is_some());
+        assert!(loss(MT::Float, MT::OneOf(vec![MT::Int, MT::Text(Plain)])).is_none());
+        assert!(loss(MT::Float, MT::OneOf(vec![MT::Int, MT::Float])).is_none());
+        assert!(loss(MT::OneOf(vec![MT::Bool, MT::Float]), MT::Int).is_some());
+
+        // The value of Any type may be a Float
+        assert!(loss(MT::Any, MT::Int).is_some());
+        assert!(loss(MT::Any, MT::Text(Plain)).is_none());
+        assert!(loss(MT::Float, MT::Any).is_none());
+    }
+
+    #[test]
+    fn test_conversion_bool_null_timestamp_result() {
+        assert_conversion_result(MT::Bool, MD::Bool(true), MT::Int, MD::Int(1));
+        assert_conversion_result(MT::Int, MD::Int(0), MT::Bool, MD::Bool(false));
+        assert_conversion_error(MT::Int, MD::Int(2), MT::Bool);
+        assert_conversion_result(
+            MT::Bool,
+            MD::Bool(false),
+            MT::Text(Plain),
+            make_md_text_plain("false"),
+        );
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("true"),
+            MT::Bool,
+            MD::Bool(true),
+        );
+        assert_conversion_error(MT::Text(Plain), make_md_text_plain("1"), MT::Bool);
+
+        assert_conversion_result(
+            MT::Null,
+            MD::Null,
+            MT::Text(Json),
+            make_md_text_json("null"),
+        );
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text_json("null"),
+            MT::Null,
+            MD::Null,
+        );
+
+        let timestamp = make_md_timestamp("2022-08-01T12:00:00.250Z");
+        assert_conversion_result(
+            MT::Timestamp,
+            timestamp.clone(),
+            MT::Int,
+            MD::Int(1659355200250),
+        );
+        assert_conversion_result(
+            MT::Int,
+            MD::Int(1659355200250),
+            MT::Timestamp,
+            timestamp.clone(),
+        );
+        assert_conversion_result(
+            MT::Timestamp,
+            timestamp.clone(),
+            MT::Text(Plain),
+            make_md_text_plain("2022-08-01T12:00:00.250Z"),
+        );
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("2022-08-01T14:00:00.25+02:00"),
+            MT::Timestamp,
+            timestamp,
+        );
+        assert_conversion_error(
+            MT::Text(Plain),
+            make_md_text_plain("2022-08-01"),
+            MT::Timestamp,
+        );
+        assert_conversion_error(MT::Int, MD::Int(i64::MAX), MT::Timestamp);
+    }
+
+    #[test]
+    fn test_conversion_bool_null_timestamp_json() {
+        let src = make_md_dict(vec![
+            ("bool", MD::Bool(true), MT::Bool),
+            ("null", MD::Null, MT::Null),
+            (
+                "timestamp",
+                make_md_timestamp("2022-08-01T12:00:00Z"),
+                MT::Timestamp,
+            ),
+        ]);
+        let json =
+            make_md_text_json(r#"{"bool":true,"null":null,"timestamp":"2022-08-01T12:00:00Z"}"#);
+        assert_conversion_result(
+            md_dict_schema(&src),
+            src.clone(),
+            MT::Text(Json),
+            json.clone(),
+        );
+        assert_conversion_result(MT::Text(Json), json, md_dict_schema(&src), src);
+    }
+
+    fn assert_explicit_conversion_result(
+        name: &str,
+        src_type: MT,
+        src: MD,
+        dst_type: MT,
+        dst_expected: MD,
+    ) {
+        let conv = conversion::find_explicit(name, &src_type, &dst_type, DEFAULT_MAX_NESTING_DEPTH);
+        assert!(conv.is_ok());
+        let dst_actual = conv.unwrap()(&src, &dst_type);
+        assert!(dst_actual.is_ok());
+        assert_eq!(dst_actual.unwrap(), dst_expected);
+    }
+
+    fn assert_explicit_conversion_error(name: &str, src_type: MT, src: MD, dst_type: MT) {
+        let conv = conversion::find_explicit(name, &src_type, &dst_type, DEFAULT_MAX_NESTING_DEPTH);
+        assert!(conv.is_ok());
+        assert!(conv.unwrap()(&src, &dst_type).is_err());
+    }
+
+    fn make_md_binary(value: &[u8]) -> MD {
+        MD::Binary(Binary {
+            value: value.to_vec(),
+            content_type: Unknown,
+        })
+    }
+
+    #[test]
+    fn test_conversion_explicit() {
+        let text = MT::Text(Plain);
+        let binary = MT::Binary(Unknown);
+
+        assert_explicit_conversion_result(
+            "utf8",
+            text.clone(),
+            make_md_text_plain("Ä"),
+            binary.clone(),
+            make_md_binary(&[0xc3, 0x84]),
+        );
+        assert_explicit_conversion_result(
+            "utf8",
+            binary.clone(),
+            make_md_binary(&[0xc3, 0x84]),
+            text.clone(),
+            make_md_text_plain("Ä"),
+        );
+        assert_explicit_conversion_error(
+            "utf8",
+            binary.clone(),
+            make_md_binary(&[0xff]),
+            text.clone(),
+        );
+
+        assert_explicit_conversion_result(
+            "base64",
+            binary.clone(),
+            make_md_binary(&[1, 2, 3]),
+            text.clone(),
+            make_md_text_plain("AQID"),
+        );
+        assert_explicit_conversion_result(
+            "base64",
+            text.clone(),
+            make_md_text_plain("AQID"),
+            binary.clone(),
+            make_md_binary(&[1, 2, 3]),
+        );
+        assert_explicit_conversion_error(
+            "base64",
+            text.clone(),
+            make_md_text_plain("*"),
+            binary.clone(),
+        );
+
+        assert_explicit_conversion_result(
+            "hex",
+            binary.clone(),
+            make_md_binary(&[0x01, 0xab]),
+            text.clone(),
+            make_md_text_plain("01ab"),
+        );
+        assert_explicit_conversion_result(
+            "hex",
+            text.clone(),
+            make_md_text_plain("01AB"),
+            binary.clone(),
+            make_md_binary(&[0x01, 0xab]),
+        );
+        assert_explicit_conversion_error(
+            "hex",
+            text.clone(),
+            make_md_text_plain("abc"),
+            binary.clone(),
+        );
+        assert_explicit_conversion_error(
+            "hex",
+            text.clone(),
+            make_md_text_plain("zz"),
+            binary.clone(),
+        );
+
+        assert_explicit_conversion_result(
+            "json",
+            text.clone(),
+            make_md_text_plain("hello"),
+            MT::Text(Json),
+            make_md_text_json("\"hello\""),
+        );
+        assert_explicit_conversion_result(
+            "json",
+            MT::Text(Json),
+            make_md_text_json("[1, 2]"),
+            list_of(MT::Int),
+            MD::List(vec![MD::Int(1), MD::Int(2)]),
+        );
+
+        assert_explicit_conversion_result("round", MT::Float, MD::Float(2.6), MT::Int, MD::Int(3));
+        assert_explicit_conversion_result(
+            "truncate",
+            MT::Float,
+            MD::Float(2.6),
+            MT::Int,
+            MD::Int(2),
+        );
+        // Values which don't fit into an integer are errors rather than saturated
+        for value in [f64::NAN, f64::INFINITY, 1e300, -1e19] {
+            assert_conversion_error(MT::Float, MD::Float(value), MT::Int);
+            assert_explicit_conversion_error("round", MT::Float, MD::Float(value), MT::Int);
+            assert_explicit_conversion_error("truncate", MT::Float, MD::Float(value), MT::Int);
+        }
+        let conv = conversion::find(&MT::Float, &MT::Int, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let err = conv(&MD::Float(1e300), &MT::Int).unwrap_err();
+        assert!(err.err_msg.contains("out of range of integer"));
+
+        assert_explicit_conversion_result(
+            "le_i32",
+            MT::Int,
+            MD::Int(-2),
+            binary.clone(),
+            make_md_binary(&[0xfe, 0xff, 0xff, 0xff]),
+        );
+        assert_explicit_conversion_result(
+            "le_i32",
+            binary.clone(),
+            make_md_binary(&[0x01, 0x02, 0, 0]),
+            MT::Int,
+            MD::Int(0x201),
+        );
+        assert_explicit_conversion_error("le_i32", MT::Int, MD::Int(1 << 40), binary.clone());
+        assert_explicit_conversion_error(
+            "le_i32",
+            binary.clone(),
+            make_md_binary(&[1, 2]),
+            MT::Int,
+        );
+    }
+
+    #[test]
+    fn test_conversion_explicit_not_applicable() {
+        assert!(conversion::find_explicit(
+            "base64",
+            &MT::Int,
+            &MT::Text(Plain),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        assert!(conversion::find_explicit(
+            "round",
+            &MT::Int,
+            &MT::Float,
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        assert!(conversion::find_explicit(
+            "le_i32",
+            &MT::Float,
+            &MT::Binary(Unknown),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        assert!(conversion::find_explicit(
+            "no_such_conversion",
+            &MT::Int,
+            &MT::Int,
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_conversion_dict_nested() {
+        let schema_nested_src = DictSchema::from([("nested_key".to_string(), MT::Int)]);
+
+        let schema_nested_dst = DictSchema::from([("nested_key".to_string(), MT::Float)]);
+
+        let schema_src = DictSchema::from([("nested".to_string(), MT::Dict(schema_nested_src))]);
+        let schema_dst = DictSchema::from([("nested".to_string(), MT::Dict(schema_nested_dst))]);
+
+        assert_has_bidirectional_conversion(MT::Dict(schema_src), MT::Dict(schema_dst));
+    }
+
+    fn nested_dict(depth: usize, leaf: MT) -> MT {
+        let mut mt = leaf;
+        for _ in 0..depth {
+            mt = MT::Dict(DictSchema::from([("k".to_string(), mt)]));
+        }
+        mt
+    }
+
+    #[test]
+    fn test_conversion_nesting_depth() {
+        let max_depth = DEFAULT_MAX_NESTING_DEPTH;
+        assert_has_bidirectional_conversion(
+            nested_dict(max_depth, MT::Int),
+            nested_dict(max_depth, MT::Float),
+        );
+        // The limit is given to the lookup
+        let (src, dst) = (nested_dict(4, MT::Int), nested_dict(4, MT::Float));
+        assert!(conversion::find(&src, &dst, 4).is_ok());
+        assert!(matches!(
+            conversion::find(&src, &dst, 3),
+            Err(FindConversionError::NestingTooDeep { max_depth: 3, .. })
+        ));
+        assert!(conversion::find_explicit("json", &src, &MT::Text(Json), 3).is_err());
+
+        // and limits the nesting of the decoded binary data: [[[1]]]
+        let cbor = make_md_binary_with_type(&[0x81, 0x81, 0x81, 0x01], Cbor);
+        let conv = conversion::find(&MT::Binary(Cbor), &MT::Text(Json), 4).unwrap();
+        assert_eq!(
+            conv(&cbor, &MT::Text(Json)).unwrap(),
+            make_md_text_json("[[[1]]]")
+        );
+        let conv = conversion::find(&MT::Binary(Cbor), &MT::Text(Json), 2).unwrap();
+        assert!(conv(&cbor, &MT::Text(Json)).is_err());
+
+        let err = conversion::check_nesting_depth(&nested_dict(3, MT::Int), 2).unwrap_err();
+        assert!(matches!(
+            err,
+            FindConversionError::NestingTooDeep { key_path, max_depth: 2 } if key_path == "k.k.k"
+        ));
+        let err = conversion::check_nesting_depth(&list_of(list_of(MT::Int)), 1).unwrap_err();
+        assert!(matches!(
+            err,
+            FindConversionError::NestingTooDeep { key_path, max_depth: 1 } if key_path == "[].[]"
+        ));
+        assert!(conversion::check_nesting_depth(&nested_dict(2, MT::Int), 2).is_ok());
+    }
+
+    #[test]
+    fn test_conversion_deep_schema() {
+        // Used to overflow the stack while looking for the conversion
+        let depth = 2000;
+        let src = nested_dict(depth, MT::Int);
+        let dst = nested_dict(depth, MT::Float);
+        let err = conversion::find(&src, &dst, DEFAULT_MAX_NESTING_DEPTH)
+            .err()
+            .unwrap();
+        let expected_path = vec!["k"; DEFAULT_MAX_NESTING_DEPTH + 1].join(".");
+        assert!(matches!(
+            err,
+            FindConversionError::NestingTooDeep { key_path, .. } if key_path == expected_path
+        ));
+        assert!(conversion::find_explicit(
+            "json",
+            &src,
+            &MT::Text(Json),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+    }
+
+    fn optional(inner: MT) -> MT {
+        MT::Optional(Box::new(inner))
+    }
+
+    #[test]
+    fn test_conversion_any() {
+        assert_has_conversion(MT::Int, MT::Any);
+        assert_has_conversion(list_of(MT::Int), list_of(MT::Any));
+        assert_conversion_result(MT::Float, MD::Float(1.5), MT::Any, MD::Float(1.5));
+
+        // Conversion from Any is chosen based on the value
+        assert_conversion_result(MT::Any, make_md_text_plain("5"), MT::Int, MD::Int(5));
+        assert_conversion_result(MT::Any, MD::Bool(true), MT::Int, MD::Int(1));
+        assert_conversion_error(MT::Any, MD::Null, MT::Int);
+    }
+
+    #[test]
+    fn test_conversion_optional() {
+        assert_has_conversion(MT::Int, optional(MT::Float));
+        assert_has_conversion(MT::Null, optional(MT::Float));
+        assert_has_conversion(optional(MT::Int), optional(MT::Float));
+        assert_has_conversion(optional(MT::Int), MT::Text(Json));
+        assert_has_no_conversion(optional(MT::Int), MT::Int);
+
+        assert_conversion_result(optional(MT::Int), MD::Null, optional(MT::Float), MD::Null);
+        assert_conversion_result(
+            optional(MT::Int),
+            MD::Int(2),
+            optional(MT::Float),
+            MD::Float(2.0),
+        );
+    }
+
+    #[test]
+    fn test_conversion_dict_optional_keys() {
+        let src = make_md_dict(vec![("a", MD::Int(1), MT::Int)]);
+        let dst_schema = DictSchema::from([
+            ("a".to_string(), MT::Float),
+            ("b".to_string(), optional(MT::Text(Plain))),
+        ]);
+        assert_has_no_conversion(
+            md_dict_schema(&src),
+            MT::Dict(DictSchema::from([("b".to_string(), MT::Text(Plain))])),
+        );
+        assert_conversion_result(
+            md_dict_schema(&src),
+            src,
+            MT::Dict(dst_schema.clone()),
+            MD::Dict(Dict {
+                data: HashMap::from([("a".to_string(), MD::Float(1.0))]),
+                schema: dst_schema.clone(),
+            }),
+        );
+
+        // Key which is optional in both the source and the destination
+        let src_schema = DictSchema::from([
+            ("a".to_string(), MT::Int),
+            ("b".to_string(), optional(MT::Int)),
+        ]);
+        let src = MD::Dict(Dict {
+            data: HashMap::from([("a".to_string(), MD::Int(1)), ("b".to_string(), MD::Int(2))]),
+            schema: src_schema.clone(),
+        });
+        let dst_expected = MD::Dict(Dict {
+            data: HashMap::from([
+                ("a".to_string(), MD::Float(1.0)),
+                ("b".to_string(), make_md_text_plain("2")),
+            ]),
+            schema: dst_schema.clone(),
+        });
+        assert_conversion_result(
+            MT::Dict(src_schema.clone()),
+            src,
+            MT::Dict(dst_schema.clone()),
+            dst_expected,
+        );
+        let src = MD::Dict(Dict {
+            data: HashMap::from([("a".to_string(), MD::Int(1))]),
+            schema: src_schema.clone(),
+        });
+        let dst_expected = MD::Dict(Dict {
+            data: HashMap::from([("a".to_string(), MD::Float(1.0))]),
+            schema: dst_schema.clone(),
+        });
+        assert_conversion_result(
+            MT::Dict(src_schema),
+            src,
+            MT::Dict(dst_schema),
+            dst_expected,
+        );
+    }
+
+    #[test]
+    fn test_conversion_one_of() {
+        let int_or_text = MT::OneOf(vec![MT::Int, MT::Text(Plain)]);
+        assert_has_conversion(MT::Float, int_or_text.clone());
+        assert_has_conversion(int_or_text.clone(), MT::Text(Plain));
+        assert_has_no_conversion(MT::Dict(DictSchema::new()), int_or_text.clone());
+        assert_has_conversion(int_or_text.clone(), MT::Int);
+        assert_has_no_conversion(int_or_text.clone(), MT::Null);
+        assert_has_no_conversion(MT::OneOf(vec![MT::Int, MT::Binary(Unknown)]), MT::Timestamp);
+
+        // The member of the same type is preferred
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("1"),
+            int_or_text.clone(),
+            make_md_text_plain("1"),
+        );
+        // Then the members which don't lose information
+        assert_conversion_result(
+            MT::Float,
+            MD::Float(2.7),
+            int_or_text.clone(),
+            make_md_text_plain("2.7"),
+        );
+        assert_conversion_result(
+            MT::Float,
+            MD::Float(2.7),
+            MT::OneOf(vec![MT::Int, MT::Bool]),
+            MD::Int(2),
+        );
+        assert_conversion_result(
+            int_or_text.clone(),
+            MD::Int(1),
+            MT::Text(Plain),
+            make_md_text_plain("1"),
+        );
+
+        // Members are tried in order until one accepts the value
+        let bool_or_int = MT::OneOf(vec![MT::Bool, MT::Int]);
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("true"),
+            bool_or_int.clone(),
+            MD::Bool(true),
+        );
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("12"),
+            bool_or_int.clone(),
+            MD::Int(12),
+        );
+        assert_conversion_error(MT::Text(Plain), make_md_text_plain("x"), bool_or_int);
+    }
+
+    #[test]
+    fn test_conversion_json_optional_any_one_of() {
+        let schema = DictSchema::from([
+            ("a".to_string(), optional(MT::Int)),
+            ("b".to_string(), MT::Any),
+            ("c".to_string(), MT::OneOf(vec![MT::Int, MT::Text(Plain)])),
+        ]);
+        let dst_type = MT::Dict(schema.clone());
+        let conv = conversion::find(&MT::Text(Json), &dst_type, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+
+        let res = conv(
+            &make_md_text_json(r#"{"b": [1, "x"], "c": "y"}"#),
+            &dst_type,
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            MD::Dict(Dict {
+                data: HashMap::from([
+                    (
+                        "b".to_string(),
+                        MD::List(vec![MD::Int(1), make_md_text_plain("x")])
+                    ),
+                    ("c".to_string(), make_md_text_plain("y")),
+                ]),
+                schema: schema.clone(),
+            })
+        );
+
+        let res = conv(
+            &make_md_text_json(r#"{"a": null, "b": {"k": 1.5}, "c": 1}"#),
+            &dst_type,
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            MD::Dict(Dict {
+                data: HashMap::from([
+                    ("a".to_string(), MD::Null),
+                    (
+                        "b".to_string(),
+                        make_md_dict(vec![("k", MD::Float(1.5), MT::Float)])
+                    ),
+                    ("c".to_string(), MD::Int(1)),
+                ]),
+                schema,
+            })
+        );
+
+        assert!(conv(&make_md_text_json(r#"{"b": 1, "c": true}"#), &dst_type).is_err());
+        assert!(conv(&make_md_text_json(r#"{"c": 1}"#), &dst_type).is_err());
+    }
+
+    fn make_md_text(text: &str, content_type: TextContentType) -> MD {
+        MD::Text(Text {
+            value: text.to_string(),
+            content_type,
+        })
+    }
+
+    #[test]
+    fn test_conversion_yaml() {
+        let schema = DictSchema::from([
+            ("a".to_string(), MT::Int),
+            ("b".to_string(), list_of(MT::Text(Plain))),
+        ]);
+        assert_has_bidirectional_conversion(MT::Dict(schema.clone()), MT::Text(Yaml));
+        assert_has_bidirectional_conversion(list_of(MT::Int), MT::Text(Yaml));
+        assert_has_no_conversion(MT::Dict(schema.clone()), MT::Text(Xml));
+
+        let md = MD::Dict(Dict {
+            data: HashMap::from([
+                ("a".to_string(), MD::Int(1)),
+                ("b".to_string(), MD::List(vec![make_md_text_plain("x")])),
+            ]),
+            schema: schema.clone(),
+        });
+        assert_conversion_result(
+            MT::Dict(schema.clone()),
+            md.clone(),
+            MT::Text(Yaml),
+            make_md_text("a: 1\nb:\n- x\n", Yaml),
+        );
+        assert_conversion_result(
+            MT::Text(Yaml),
+            make_md_text("b: [x]\na: 1\n", Yaml),
+            MT::Dict(schema.clone()),
+            md,
+        );
+        assert_conversion_error(MT::Text(Yaml), make_md_text("a: [", Yaml), MT::Dict(schema));
+    }
+
+    #[test]
+    fn test_conversion_csv() {
+        let schema = DictSchema::from([
+            ("name".to_string(), MT::Text(Plain)),
+            ("count".to_string(), MT::Int),
+            ("ratio".to_string(), optional(MT::Float)),
+        ]);
+        let rows_type = list_of(MT::Dict(schema.clone()));
+        assert_has_bidirectional_conversion(rows_type.clone(), MT::Text(Csv));
+        assert_has_no_conversion(list_of(MT::Int), MT::Text(Csv));
+        assert_has_no_conversion(
+            list_of(MT::Dict(DictSchema::from([(
+                "nested".to_string(),
+                list_of(MT::Int),
+            )]))),
+            MT::Text(Csv),
+        );
+        assert_has_no_conversion(MT::Dict(schema.clone()), MT::Text(Csv));
+
+        let rows = MD::List(vec![
+            MD::Dict(Dict {
+                data: HashMap::from([
+                    ("name".to_string(), make_md_text_plain("a, b")),
+                    ("count".to_string(), MD::Int(1)),
+                    ("ratio".to_string(), MD::Float(0.5)),
+                ]),
+                schema: schema.clone(),
+            }),
+            MD::Dict(Dict {
+                data: HashMap::from([
+                    ("name".to_string(), make_md_text_plain("c")),
+                    ("count".to_string(), MD::Int(2)),
+                ]),
+                schema: schema.clone(),
+            }),
+        ]);
+        let csv = make_md_text("count,name,ratio\n1,\"a, b\",0.5\n2,c,\n", Csv);
+        assert_conversion_result(rows_type.clone(), rows.clone(), MT::Text(Csv), csv.clone());
+        assert_conversion_result(MT::Text(Csv), csv, rows_type.clone(), rows);
+
+        // Columns are matched by name, extra columns are ignored
+        let conv = conversion::find(&MT::Text(Csv), &rows_type, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let res = conv(&make_md_text("x,name,count\n0,a,1\n", Csv), &rows_type).unwrap();
+        assert_eq!(
+            res,
+            MD::List(vec![MD::Dict(Dict {
+                data: HashMap::from([
+                    ("name".to_string(), make_md_text_plain("a")),
+                    ("count".to_string(), MD::Int(1)),
+                ]),
+                schema,
+            })])
+        );
+
+        let err = conv(&make_md_text("name,count\na,1\nb,x\n", Csv), &rows_type).unwrap_err();
+        assert_eq!(err.key_path, Some("1.count".to_string()));
+        let err = conv(&make_md_text("name\na\n", Csv), &rows_type).unwrap_err();
+        assert_eq!(err.key_path, Some("0.count".to_string()));
+    }
+
+    fn make_md_binary_with_type(value: &[u8], content_type: BinaryContentType) -> MD {
+        MD::Binary(Binary {
+            value: value.to_vec(),
+            content_type,
+        })
+    }
+
+    #[test]
+    fn test_conversion_cbor_msgpack() {
+        for content_type in [Cbor, MessagePack] {
+            let binary = MT::Binary(content_type.clone());
+            let src = make_md_dict_all_types();
+            let schema = md_dict_schema(&src);
+            assert_has_bidirectional_conversion(schema.clone(), binary.clone());
+            assert_has_bidirectional_conversion(list_of(MT::Timestamp), binary.clone());
+            assert_has_bidirectional_conversion(MT::Int, binary.clone());
+            assert_has_bidirectional_conversion(MT::Null, binary.clone());
+            assert_has_no_conversion(MT::Int, MT::Binary(OctetStream));
+            assert_has_no_conversion(MT::Binary(OctetStream), MT::Int);
+
+            let roundtrip = |src_type: &MT, md: &MD| {
+                let encoded = conversion::find(src_type, &binary, DEFAULT_MAX_NESTING_DEPTH)
+                    .unwrap()(md, &binary)
+                .unwrap();
+                conversion::find(&binary, src_type, DEFAULT_MAX_NESTING_DEPTH).unwrap()(
+                    &encoded, src_type,
+                )
+                .unwrap()
+            };
+            assert_eq!(roundtrip(&schema, &src), src);
+            let list = MD::List(vec![
+                make_md_timestamp("2022-08-01T12:00:00Z"),
+                make_md_timestamp("2022-08-01T12:00:00.123456789Z"),
+            ]);
+            assert_eq!(roundtrip(&list_of(MT::Timestamp), &list), list);
+            assert_eq!(roundtrip(&MT::Int, &MD::Int(-5)), MD::Int(-5));
+            assert_eq!(
+                roundtrip(&MT::Text(Json), &make_md_text(r#"{"a":[1,"x"]}"#, Json)),
+                make_md_text(r#"{"a":[1,"x"]}"#, Json)
+            );
+            // Only JSON and YAML text can hold the decoded value directly
+            assert!(
+                conversion::find_builtin(&binary, &MT::Text(Csv), DEFAULT_MAX_NESTING_DEPTH)
+                    .is_err()
+            );
+
+            // Not a complete value
+            assert_conversion_error(
+                binary.clone(),
+                make_md_binary_with_type(&[0x81], content_type.clone()),
+                list_of(MT::Int),
+            );
+        }
+
+        let dst_type = MT::Dict(DictSchema::from([("a".to_string(), MT::Int)]));
+        let dst = make_md_dict(vec![("a", MD::Int(1), MT::Int)]);
+        assert_conversion_result(
+            dst_type.clone(),
+            dst.clone(),
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xa1, 0x61, 0x61, 0x01], Cbor),
+        );
+        assert_conversion_result(
+            MT::Binary(MessagePack),
+            make_md_binary_with_type(&[0x81, 0xa1, 0x61, 0x01], MessagePack),
+            dst_type.clone(),
+            dst,
+        );
+        // {"a": "x"}
+        assert_conversion_error(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xa1, 0x61, 0x61, 0x61, 0x78], Cbor),
+            dst_type,
+        );
+        // Maps are written as JSON or YAML text
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xa1, 0x61, 0x61, 0x01], Cbor),
+            MT::Text(Json),
+            make_md_text(r#"{"a":1}"#, Json),
+        );
+        assert_conversion_result(
+            MT::Binary(MessagePack),
+            make_md_binary_with_type(&[0x81, 0xa1, 0x61, 0x01], MessagePack),
+            MT::Text(Yaml),
+            make_md_text("a: 1\n", Yaml),
+        );
+        // Transcoded between CBOR and MessagePack, relabeled to and from unstructured data
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xa1, 0x61, 0x61, 0x01], Cbor),
+            MT::Binary(MessagePack),
+            make_md_binary_with_type(&[0x81, 0xa1, 0x61, 0x01], MessagePack),
+        );
+        assert_conversion_result(
+            MT::Binary(MessagePack),
+            make_md_binary_with_type(&[0x81, 0xa1, 0x61, 0x01], MessagePack),
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xa1, 0x61, 0x61, 0x01], Cbor),
+        );
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[1], Cbor),
+            MT::Binary(OctetStream),
+            make_md_binary_with_type(&[1], OctetStream),
+        );
+        // CBOR epoch-based date/time
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xc1, 0x1a, 0x62, 0xe7, 0xc0, 0x40], Cbor),
+            MT::Timestamp,
+            make_md_timestamp("2022-08-01T12:00:00Z"),
+        );
+        // -1.5 s as a half-precision float
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[0xc1, 0xf9, 0xbe, 0x00], Cbor),
+            MT::Timestamp,
+            make_md_timestamp("1969-12-31T23:59:58.5Z"),
+        );
+        // 1 ns before the epoch, rounded
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(
+                &[0xc1, 0xfb, 0xbe, 0x11, 0x2e, 0x0b, 0xe8, 0x26, 0xd6, 0x95],
+                Cbor,
+            ),
+            MT::Timestamp,
+            make_md_timestamp("1969-12-31T23:59:59.999999999Z"),
+        );
+        // Rounded up to the next second
+        assert_conversion_result(
+            MT::Binary(Cbor),
+            make_md_binary_with_type(
+                &[0xc1, 0xfb, 0xbd, 0xdb, 0x7c, 0xdf, 0xd9, 0xd7, 0xbd, 0xbb],
+                Cbor,
+            ),
+            MT::Timestamp,
+            make_md_timestamp("1970-01-01T00:00:00Z"),
+        );
+        // NaN and infinity
+        for bytes in [[0xc1, 0xf9, 0x7e, 0x00], [0xc1, 0xf9, 0x7c, 0x00]] {
+            assert_conversion_error(
+                MT::Binary(Cbor),
+                make_md_binary_with_type(&bytes, Cbor),
+                MT::Timestamp,
+            );
+        }
+    }
+
+    #[test]
+    fn test_conversion_text_binary() {
+        for content_type in [Base64, Hex, Plain] {
+            assert_has_bidirectional_conversion(MT::Text(content_type), MT::Binary(OctetStream));
+        }
+        let binary = make_md_binary_with_type(&[0x68, 0x69, 0xff], OctetStream);
+        assert_conversion_result(
+            MT::Binary(OctetStream),
+            binary.clone(),
+            MT::Text(Base64),
+            make_md_text("aGn/", Base64),
+        );
+        assert_conversion_result(
+            MT::Text(Base64),
+            make_md_text("aGn/\n", Base64),
+            MT::Binary(OctetStream),
+            binary.clone(),
+        );
+        assert_conversion_result(
+            MT::Binary(OctetStream),
+            binary.clone(),
+            MT::Text(Hex),
+            make_md_text("6869ff", Hex),
+        );
+        assert_conversion_result(
+            MT::Text(Hex),
+            make_md_text("6869FF", Hex),
+            MT::Binary(OctetStream),
+            binary.clone(),
+        );
+        assert_conversion_error(
+            MT::Text(Hex),
+            make_md_text("686", Hex),
+            MT::Binary(OctetStream),
+        );
+        assert_conversion_error(
+            MT::Text(Base64),
+            make_md_text("a?", Base64),
+            MT::Binary(OctetStream),
+        );
+
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("hi"),
+            MT::Binary(Unknown),
+            make_md_binary(b"hi"),
+        );
+        assert_conversion_result(
+            MT::Binary(Unknown),
+            make_md_binary(b"hi"),
+            MT::Text(Plain),
+            make_md_text_plain("hi"),
+        );
+        let conv = conversion::find(
+            &MT::Binary(OctetStream),
+            &MT::Text(Plain),
+            DEFAULT_MAX_NESTING_DEPTH,
+        )
+        .unwrap();
+        let err = conv(&binary, &MT::Text(Plain)).err().unwrap();
+        assert!(err.err_msg.starts_with("binary data is not valid UTF-8"));
+
+        // Base64 text holding CBOR data is decoded, not encoded as a CBOR string
+        assert_conversion_result(
+            MT::Text(Base64),
+            make_md_text("AQ==", Base64),
+            MT::Binary(Cbor),
+            make_md_binary_with_type(&[1], Cbor),
+        );
+    }
+
+    fn make_md_fixed_int(value: i128, int_type: IntType) -> MD {
+        MD::FixedInt(FixedInt::new(value, int_type).unwrap())
+    }
+
+    #[test]
+    fn test_conversion_text_to_text() {
+        // Base64 and hex text are transcoded through the bytes they hold
+        assert_conversion_result(
+            MT::Text(Base64),
+            make_md_text("aGn/", Base64),
+            MT::Text(Hex),
+            make_md_text("6869ff", Hex),
+        );
+        assert_conversion_result(
+            MT::Text(Hex),
+            make_md_text("6869ff", Hex),
+            MT::Text(Base64),
+            make_md_text("aGn/", Base64),
+        );
+        assert_conversion_error(MT::Text(Hex), make_md_text("686", Hex), MT::Text(Base64));
+
+        // JSON and YAML text are transcoded through the parsed value
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text(r#"{"a":[1,"x"]}"#, Json),
+            MT::Text(Yaml),
+            make_md_text("a:\n- 1\n- x\n", Yaml),
+        );
+        assert_conversion_result(
+            MT::Text(Yaml),
+            make_md_text("a:\n- 1\n- x\n", Yaml),
+            MT::Text(Json),
+            make_md_text(r#"{"a":[1,"x"]}"#, Json),
+        );
+        assert_conversion_error(MT::Text(Json), make_md_text("{", Json), MT::Text(Yaml));
+
+        // Any text is plain text, but plain text isn't assumed to hold anything more specific
+        assert_conversion_result(
+            MT::Text(Csv),
+            make_md_text("a,b\n", Csv),
+            MT::Text(Plain),
+            make_md_text("a,b\n", Plain),
+        );
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text("{}", Json),
+            MT::Text(Json),
+            make_md_text("{}", Json),
+        );
+        assert!(matches!(
+            conversion::find(&MT::Text(Plain), &MT::Text(Json), DEFAULT_MAX_NESTING_DEPTH),
+            Err(FindConversionError::NoImplicitConversion)
+        ));
+        assert_has_no_conversion(MT::Text(Plain), MT::Text(Base64));
+        assert_has_no_conversion(MT::Text(Csv), MT::Text(Json));
+        assert_has_no_conversion(MT::Text(Base64), MT::Text(Yaml));
+    }
+    #[test]
+    fn test_conversion_fixed_int() {
+        let uint8 = MT::FixedInt(IntType::U8);
+        let int16 = MT::FixedInt(IntType::I16);
+        let uint64 = MT::FixedInt(IntType::U64);
+        assert_has_bidirectional_conversion(uint8.clone(), MT::Int);
+        assert_has_bidirectional_conversion(uint8.clone(), int16.clone());
+        assert_has_bidirectional_conversion(uint8.clone(), MT::Float);
+        assert_has_bidirectional_conversion(uint8.clone(), MT::Text(Plain));
+        assert_has_no_conversion(uint8.clone(), MT::Bool);
+        assert!(conversion::find_loss(&MT::Float, &uint8, DEFAULT_MAX_NESTING_DEPTH).is_some());
+        assert!(conversion::find_loss(&uint64, &MT::Float, DEFAULT_MAX_NESTING_DEPTH).is_some());
+        assert!(conversion::find_loss(
+            &MT::FixedInt(IntType::U32),
+            &MT::Float,
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_none());
+
+        assert_conversion_result(
+            MT::Int,
+            MD::Int(255),
+            uint8.clone(),
+            make_md_fixed_int(255, IntType::U8),
+        );
+        assert_conversion_result(
+            uint8.clone(),
+            make_md_fixed_int(200, IntType::U8),
+            int16.clone(),
+            make_md_fixed_int(200, IntType::I16),
+        );
+        assert_conversion_result(
+            MT::Float,
+            MD::Float(-1.7),
+            int16.clone(),
+            make_md_fixed_int(-1, IntType::I16),
+        );
+        assert_conversion_result(
+            MT::Text(Plain),
+            make_md_text_plain("18446744073709551615"),
+            uint64.clone(),
+            make_md_fixed_int(u64::MAX.into(), IntType::U64),
+        );
+
+        let conv = conversion::find(&MT::Int, &uint8, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let err = conv(&MD::Int(256), &uint8).err().unwrap();
+        assert_eq!(err.err_msg, "value 256 is out of range of uint8 (0 to 255)");
+        assert_conversion_error(MT::Int, MD::Int(-1), uint8.clone());
+        assert_conversion_error(
+            int16.clone(),
+            make_md_fixed_int(-1, IntType::I16),
+            uint8.clone(),
+        );
+        assert_conversion_error(
+            uint64.clone(),
+            make_md_fixed_int(u64::MAX.into(), IntType::U64),
+            MT::Int,
+        );
+        assert_conversion_error(MT::Float, MD::Float(f64::NAN), uint8.clone());
+        assert_conversion_error(MT::Float, MD::Float(1e300), uint64);
+
+        // JSON values are range checked too
+        let schema = DictSchema::from([("r".to_string(), uint8)]);
+        assert_conversion_result(
+            MT::Text(Json),
+            make_md_text_json(r#"{"r": 7}"#),
+            MT::Dict(schema.clone()),
+            make_md_dict(vec![(
+                "r",
+                make_md_fixed_int(7, IntType::U8),
+                MT::FixedInt(IntType::U8),
+            )]),
+        );
+        assert_conversion_error(
+            MT::Text(Json),
+            make_md_text_json(r#"{"r": 300}"#),
+            MT::Dict(schema),
+        );
+    }
+
+    #[test]
+    fn test_conversion_float_precision() {
+        // Integers up to 2^53 are exact
+        let big = 1i64 << 40;
+        assert_conversion_result(
+            MT::Int,
+            MD::Int(big + 1),
+            MT::Float,
+            MD::Float((big + 1) as f64),
+        );
+        assert_conversion_result(
+            MT::Float,
+            MD::Float(0.1),
+            MT::Text(Plain),
+            make_md_text_plain("0.1"),
+        );
     }
 }
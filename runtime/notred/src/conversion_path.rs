@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use crate::conversion::{check_nesting_depth, find_builtin, find_loss};
+use crate::message::MessageType as MT;
+use crate::message::*;
+
+/// Cost of a single conversion step.
+const STEP_COST: u32 = 1;
+/// Additional cost of a step which may lose information.
+const LOSSY_STEP_COST: u32 = 10;
+
+/// Types which may appear in the middle of a conversion chain, in addition to the source and the
+/// destination types.
+fn intermediate_types() -> Vec<MT> {
+    vec![
+        MT::Text(TextContentType::Plain),
+        MT::Text(TextContentType::Json),
+        MT::Binary(BinaryContentType::Unknown),
+        MT::Int,
+        MT::Float,
+        MT::Bool,
+        MT::Null,
+        MT::Timestamp,
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StepKind {
+    /// Text to text or binary to binary, only changing the content type
+    Relabel,
+    /// Serializing a value into text or binary
+    Encode,
+    /// Parsing text or binary into a value
+    Decode,
+    /// Changing the type of the value, e.g. Int to Float
+    Cast,
+}
+
+fn step_kind(src: &MT, dst: &MT) -> StepKind {
+    match (src, dst) {
+        (MT::Text(_), MT::Text(_)) | (MT::Binary(_), MT::Binary(_)) => StepKind::Relabel,
+        (MT::Text(_) | MT::Binary(_), _) => StepKind::Decode,
+        (_, MT::Text(_) | MT::Binary(_)) => StepKind::Encode,
+        _ => StepKind::Cast,
+    }
+}
+
+/// State of the search: the current type, and what the chain has done so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    type_index: usize,
+    encoded: bool,
+    decoded: bool,
+    cast: bool,
+}
+
+impl State {
+    /// Returns the state after taking a step of the given kind, or None if the chain may not
+    /// continue with such a step.
+    ///
+    /// The restrictions keep the chains meaningful: text produced by an earlier step is never
+    /// parsed again (so Float -> Text -> Bool is not allowed), text and binary content types may
+    /// only be relabeled to the generic ones (so Text(Plain) -> Text(Json) -> Dict is not
+    /// allowed), and a chain contains at most one decoding step and one cast (so
+    /// Binary -> Text(Plain) -> Int and Bool -> Int -> Timestamp are not allowed).
+    fn step(&self, kind: StepKind, dst: &MT, dst_index: usize) -> Option<State> {
+        let allowed = match kind {
+            StepKind::Relabel => matches!(
+                dst,
+                MT::Text(TextContentType::Plain) | MT::Binary(BinaryContentType::Unknown)
+            ),
+            StepKind::Decode | StepKind::Cast if self.encoded => false,
+            StepKind::Decode => !self.decoded,
+            StepKind::Cast => !self.cast,
+            StepKind::Encode => true,
+        };
+        if !allowed {
+            return None;
+        }
+        Some(State {
+            type_index: dst_index,
+            encoded: self.encoded || kind == StepKind::Encode,
+            decoded: self.decoded || kind == StepKind::Decode,
+            cast: self.cast || kind == StepKind::Cast,
+        })
+    }
+}
+
+struct SearchNode {
+    state: State,
+    cost: u32,
+    visited: bool,
+    prev: Option<(usize, MessageConverter)>,
+}
+
+/// Finds the cheapest chain of built-in implicit conversions from `src` to `dst`.
+///
+/// The direct conversion is used when it exists. Otherwise, the chain is searched among the
+/// simple types using Dijkstra's algorithm, with lossy steps being more expensive. If no chain
+/// exists, the error of the direct lookup is returned.
+///
+/// Types nested deeper than `max_depth` are rejected, and the converters decode binary data nested
+/// at most as deep.
+pub(crate) fn find_path(
+    src: &MT,
+    dst: &MT,
+    max_depth: usize,
+) -> Result<ConversionPath, FindConversionError> {
+    check_nesting_depth(src, max_depth)?;
+    check_nesting_depth(dst, max_depth)?;
+    let direct_err = match find_builtin(src, dst, max_depth) {
+        Ok(conv) => return Ok(compose(src, vec![(conv, dst.clone())], max_depth)),
+        Err(e) => e,
+    };
+    // Text and binary content types are only converted directly, not by decoding some value
+    // and encoding it again
+    if let (MT::Text(_), MT::Text(_)) | (MT::Binary(_), MT::Binary(_)) = (src, dst) {
+        return Err(direct_err);
+    }
+
+    let mut types = vec![src.clone(), dst.clone()];
+    for t in intermediate_types() {
+        if !types.contains(&t) {
+            types.push(t);
+        }
+    }
+    let dst_index = 1;
+
+    let mut nodes = vec![SearchNode {
+        state: State {
+            type_index: 0,
+            encoded: false,
+            decoded: false,
+            cast: false,
+        },
+        cost: 0,
+        visited: false,
+        prev: None,
+    }];
+    loop {
+        let current = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| !n.visited)
+            .min_by_key(|(_, n)| n.cost)
+            .map(|(i, _)| i);
+        let current = match current {
+            Some(current) => current,
+            None => return Err(direct_err),
+        };
+        nodes[current].visited = true;
+        let state = nodes[current].state;
+        if state.type_index == dst_index {
+            return Ok(compose(
+                src,
+                collect_steps(&nodes, &types, current),
+                max_depth,
+            ));
+        }
+
+        let mt_src = &types[state.type_index];
+        for (next_index, mt_dst) in types.iter().enumerate() {
+            if next_index == state.type_index {
+                continue;
+            }
+            let conv = match find_builtin(mt_src, mt_dst, max_depth) {
+                Ok(conv) => conv,
+                Err(_) => continue,
+            };
+            let next_state = match state.step(step_kind(mt_src, mt_dst), mt_dst, next_index) {
+                Some(next_state) => next_state,
+                None => continue,
+            };
+            let mut cost = nodes[current].cost + STEP_COST;
+            if find_loss(mt_src, mt_dst, max_depth).is_some() {
+                cost += LOSSY_STEP_COST;
+            }
+            match nodes.iter_mut().find(|n| n.state == next_state) {
+                Some(n) if n.visited || n.cost <= cost => {}
+                Some(n) => {
+                    n.cost = cost;
+                    n.prev = Some((current, conv));
+                }
+                None => nodes.push(SearchNode {
+                    state: next_state,
+                    cost,
+                    visited: false,
+                    prev: Some((current, conv)),
+                }),
+            }
+        }
+    }
+}
+
+fn collect_steps(nodes: &[SearchNode], types: &[MT], last: usize) -> Vec<(MessageConverter, MT)> {
+    let mut steps = Vec::new();
+    let mut current = last;
+    while let Some((prev, conv)) = &nodes[current].prev {
+        steps.push((conv.clone(), types[nodes[current].state.type_index].clone()));
+        current = *prev;
+    }
+    steps.reverse();
+    steps
+}
+
+fn compose(src: &MT, steps: Vec<(MessageConverter, MT)>, max_depth: usize) -> ConversionPath {
+    let types: Vec<MT> = steps.iter().map(|(_, mt)| mt.clone()).collect();
+    let losses: Vec<String> = std::iter::once(src)
+        .chain(types.iter())
+        .zip(types.iter())
+        .filter_map(|(mt_src, mt_dst)| find_loss(mt_src, mt_dst, max_depth))
+        .collect();
+    let loss = if losses.is_empty() {
+        None
+    } else {
+        Some(losses.join("; "))
+    };
+    let converter: MessageConverter = if steps.len() == 1 {
+        steps[0].0.clone()
+    } else {
+        Arc::new(move |src: &MessageData, _dst: &MT| {
+            let mut md = src.clone();
+            for (conv, mt) in &steps {
+                md = conv(&md, mt)?;
+            }
+            Ok(md)
+        })
+    };
+    ConversionPath {
+        steps: types,
+        converter,
+        loss,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::MessageData as MD;
+    use crate::TextContentType::*;
+
+    fn assert_path(src: MT, dst: MT, expected_steps: Vec<MT>) {
+        let path = find_path(&src, &dst, DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        assert_eq!(path.steps, expected_steps);
+    }
+
+    #[test]
+    fn test_path_direct() {
+        assert_path(MT::Int, MT::Float, vec![MT::Float]);
+        assert_path(MT::Text(Plain), MT::Int, vec![MT::Int]);
+    }
+
+    #[test]
+    fn test_path_multi_hop() {
+        let dict = MT::Dict(DictSchema::from([("key".to_string(), MT::Int)]));
+        assert_path(
+            dict.clone(),
+            MT::Text(Plain),
+            vec![MT::Text(Json), MT::Text(Plain)],
+        );
+
+        let path = find_path(&dict, &MT::Text(Plain), DEFAULT_MAX_NESTING_DEPTH).unwrap();
+        let src = MD::Dict(Dict {
+            data: [("key".to_string(), MD::Int(1))].into(),
+            schema: DictSchema::from([("key".to_string(), MT::Int)]),
+        });
+        let res = (path.converter)(&src, &MT::Text(Plain)).unwrap();
+        assert_eq!(res.as_text().unwrap(), r#"{"key":1}"#);
+    }
+
+    #[test]
+    fn test_path_null_to_text() {
+        assert_path(
+            MT::Null,
+            MT::Text(Plain),
+            vec![MT::Text(Json), MT::Text(Plain)],
+        );
+    }
+
+    #[test]
+    fn test_path_restrictions() {
+        // text produced by the chain isn't parsed again
+        assert!(find_path(&MT::Float, &MT::Bool, DEFAULT_MAX_NESTING_DEPTH).is_err());
+        assert!(find_path(&MT::Timestamp, &MT::Float, DEFAULT_MAX_NESTING_DEPTH).is_err());
+        // text isn't relabeled into JSON to be parsed
+        assert!(find_path(&MT::Text(Plain), &MT::Null, DEFAULT_MAX_NESTING_DEPTH).is_err());
+        assert!(find_path(
+            &MT::Text(Plain),
+            &MT::Dict(DictSchema::new()),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        // bytes aren't decoded as text to be parsed
+        assert!(find_path(
+            &MT::Binary(BinaryContentType::Unknown),
+            &MT::Int,
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        assert!(find_path(
+            &MT::Int,
+            &MT::Binary(BinaryContentType::Unknown),
+            DEFAULT_MAX_NESTING_DEPTH
+        )
+        .is_err());
+        // text isn't parsed to be written as text of another content type
+        assert!(find_path(&MT::Text(Plain), &MT::Text(Json), DEFAULT_MAX_NESTING_DEPTH).is_err());
+        // at most one cast
+        assert!(find_path(&MT::Bool, &MT::Timestamp, DEFAULT_MAX_NESTING_DEPTH).is_err());
+        assert!(find_path(&MT::Bool, &MT::Float, DEFAULT_MAX_NESTING_DEPTH).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::conversion::check_nesting_depth;
+use crate::message::*;
+
+type ConversionPredicate = Box<dyn Fn(&MessageType, &MessageType) -> bool + Send + Sync>;
+
+enum ConversionMatcher {
+    Types(MessageType, MessageType),
+    Predicate(ConversionPredicate),
+}
+
+struct RegisteredConversion {
+    matcher: ConversionMatcher,
+    converter: MessageConverter,
+    loss: Option<String>,
+}
+
+/// ConversionRegistry holds the conversions registered by the host application.
+///
+/// Registered conversions are consulted in the order of registration, before the built-in
+/// implicit conversions. They apply to the types of the connection endpoints; conversions of
+/// nested dictionary keys and list elements are still done by the built-in converters.
+#[derive(Default)]
+pub struct ConversionRegistry {
+    conversions: Vec<RegisteredConversion>,
+}
+
+impl ConversionRegistry {
+    pub fn new() -> ConversionRegistry {
+        ConversionRegistry::default()
+    }
+
+    /// Registers a converter from `src` type to `dst` type.
+    ///
+    /// `loss` describes the information the conversion may lose, None if it is lossless. Like the
+    /// loss of a built-in conversion, it is reported when checking the flow, and makes strict flows
+    /// reject the connections which don't allow lossy conversions.
+    pub fn register<F>(
+        &mut self,
+        src: MessageType,
+        dst: MessageType,
+        loss: Option<&str>,
+        converter: F,
+    ) where
+        F: Fn(&MessageData, &MessageType) -> ConversionResult + Send + Sync + 'static,
+    {
+        self.conversions.push(RegisteredConversion {
+            matcher: ConversionMatcher::Types(src, dst),
+            converter: Arc::new(converter),
+            loss: loss.map(str::to_string),
+        });
+    }
+
+    /// Registers a converter used for all the (source, destination) type pairs for which
+    /// `predicate` returns true. `loss` is the same as for [`register`](Self::register).
+    pub fn register_if<P, F>(&mut self, predicate: P, loss: Option<&str>, converter: F)
+    where
+        P: Fn(&MessageType, &MessageType) -> bool + Send + Sync + 'static,
+        F: Fn(&MessageData, &MessageType) -> ConversionResult + Send + Sync + 'static,
+    {
+        self.conversions.push(RegisteredConversion {
+            matcher: ConversionMatcher::Predicate(Box::new(predicate)),
+            converter: Arc::new(converter),
+            loss: loss.map(str::to_string),
+        });
+    }
+
+    /// Finds the conversion from `src` to `dst`, trying the registered conversions first.
+    ///
+    /// Types nested deeper than `max_depth` are rejected, whether or not a registered conversion
+    /// matches them, see [`find_conversion`].
+    pub fn find(
+        &self,
+        src: &MessageType,
+        dst: &MessageType,
+        max_depth: usize,
+    ) -> FindConversionResult {
+        self.find_path(src, dst, max_depth)
+            .map(|path| path.converter)
+    }
+
+    /// Same as [`find`](Self::find), but also returns the intermediate types of the conversion,
+    /// and what it may lose. A registered conversion is always a single step.
+    pub fn find_path(
+        &self,
+        src: &MessageType,
+        dst: &MessageType,
+        max_depth: usize,
+    ) -> Result<ConversionPath, FindConversionError> {
+        check_nesting_depth(src, max_depth)?;
+        check_nesting_depth(dst, max_depth)?;
+        for c in &self.conversions {
+            let matches = match &c.matcher {
+                ConversionMatcher::Types(mt_src, mt_dst) => mt_src == src && mt_dst == dst,
+                ConversionMatcher::Predicate(predicate) => predicate(src, dst),
+            };
+            if matches {
+                return Ok(ConversionPath {
+                    steps: vec![dst.clone()],
+                    converter: c.converter.clone(),
+                    loss: c.loss.clone(),
+                });
+            }
+        }
+        find_conversion_path(src, dst, max_depth)
+    }
+}
+
+impl Debug for ConversionRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConversionRegistry")
+            .field("conversions", &self.conversions.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BinaryContentType::Unknown;
+    use crate::TextContentType::Plain;
+
+    fn make_md_binary(value: &[u8]) -> MessageData {
+        MessageData::Binary(Binary {
+            value: value.to_vec(),
+            content_type: Unknown,
+        })
+    }
+
+    #[test]
+    fn test_registry_types() {
+        let mut registry = ConversionRegistry::new();
+        assert!(registry
+            .find(
+                &MessageType::Int,
+                &MessageType::Binary(Unknown),
+                DEFAULT_MAX_NESTING_DEPTH
+            )
+            .is_err());
+
+        let width = 2;
+        registry.register(
+            MessageType::Int,
+            MessageType::Binary(Unknown),
+            Some("values are truncated to 2 bytes"),
+            move |src, _dst| match src {
+                MessageData::Int(i) => Ok(make_md_binary(&i.to_be_bytes()[8 - width..])),
+                _ => unreachable!(),
+            },
+        );
+        let conv = registry
+            .find(
+                &MessageType::Int,
+                &MessageType::Binary(Unknown),
+                DEFAULT_MAX_NESTING_DEPTH,
+            )
+            .unwrap();
+        assert_eq!(
+            conv(&MessageData::Int(0x102), &MessageType::Binary(Unknown)).unwrap(),
+            make_md_binary(&[1, 2])
+        );
+        let path = registry
+            .find_path(
+                &MessageType::Int,
+                &MessageType::Binary(Unknown),
+                DEFAULT_MAX_NESTING_DEPTH,
+            )
+            .unwrap();
+        assert_eq!(
+            path.loss.as_deref(),
+            Some("values are truncated to 2 bytes")
+        );
+
+        // Built-in conversions are still available
+        assert!(registry
+            .find(
+                &MessageType::Int,
+                &MessageType::Text(Plain),
+                DEFAULT_MAX_NESTING_DEPTH
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_registry_overrides_builtin() {
+        let mut registry = ConversionRegistry::new();
+        registry.register_if(
+            |src, dst| *src == MessageType::Int && matches!(dst, MessageType::Text(_)),
+            None,
+            |src, _dst| Ok(MessageData::from_string(&format!("#{src}"))),
+        );
+        let conv = registry
+            .find(
+                &MessageType::Int,
+                &MessageType::Text(Plain),
+                DEFAULT_MAX_NESTING_DEPTH,
+            )
+            .unwrap();
+        assert_eq!(
+            conv(&MessageData::Int(5), &MessageType::Text(Plain)).unwrap(),
+            MessageData::from_str("#5")
+        );
+        let path = registry
+            .find_path(
+                &MessageType::Int,
+                &MessageType::Text(Plain),
+                DEFAULT_MAX_NESTING_DEPTH,
+            )
+            .unwrap();
+        assert_eq!(path.loss, None);
+    }
+
+    #[test]
+    fn test_registry_nesting_depth() {
+        let nested = |inner: MessageType| {
+            MessageType::Dict(DictSchema::from([(
+                "k".to_string(),
+                MessageType::Dict(DictSchema::from([("k".to_string(), inner)])),
+            )]))
+        };
+        let mut registry = ConversionRegistry::new();
+        registry.register_if(|_src, _dst| true, None, |src, _dst| Ok(src.clone()));
+        let (src, dst) = (nested(MessageType::Int), nested(MessageType::Float));
+        assert!(registry.find_path(&src, &dst, 2).is_ok());
+        // A registered conversion doesn't bypass the nesting limit
+        let err = registry.find_path(&src, &dst, 1).err().unwrap();
+        assert!(matches!(
+            err,
+            FindConversionError::NestingTooDeep { key_path, max_depth: 1 } if key_path == "k.k"
+        ));
+        assert!(registry.find(&MessageType::Int, &dst, 1).is_err());
+    }
+}
@@ -1,7 +1,6 @@
 use std::sync::mpsc::RecvTimeoutError;
 
 use quick_error::quick_error;
-use serde_json;
 
 quick_error! {
     #[derive(Debug)]
@@ -24,6 +23,15 @@ quick_error! {
         ConversionError(reason: String) {
             display("Conversion error: {}", reason)
         }
+        TypeError(reason: String) {
+            display("Type error: {}", reason)
+        }
+        QueueFull(capacity: usize) {
+            display("Event queue is full (capacity {})", capacity)
+        }
+        NodeStopped(name: String) {
+            display("Node {} has stopped", name)
+        }
 
     }
 }
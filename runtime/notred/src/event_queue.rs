@@ -0,0 +1,341 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::Event;
+use crate::errors::Error;
+use crate::executor::Completion;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 100;
+
+/// Minimum interval between two log messages about queue overflows.
+const OVERFLOW_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What happens to an event dispatched while the event queue is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// The producer waits until there is space in the queue.
+    ///
+    /// The thread running the flow can't wait for itself, so the events it dispatches (e.g. the
+    /// outputs of the nodes) are queued beyond the capacity instead. Until the flow runs, this is
+    /// the thread which built it.
+    #[default]
+    Block,
+    /// The dispatched event is dropped.
+    DropNewest,
+    /// The oldest event in the queue is dropped to make space for the dispatched one.
+    DropOldest,
+    /// The dispatched event is dropped and dispatch() returns Error::QueueFull.
+    Error,
+}
+
+/// Settings of the event queue, from the "queue" object of the flow file or FlowStateBuilder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueConfig {
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_QUEUE_CAPACITY
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+struct QueueState {
+    events: VecDeque<Event>,
+    completions: VecDeque<Completion>,
+    /// Thread running the flow: the one which created the queue, until another one waits for events
+    consumer: ThreadId,
+    overflows: u64,
+    reported_overflows: u64,
+    last_report: Option<Instant>,
+}
+
+/// Item returned by EventQueue::pop_timeout.
+pub(crate) enum QueueItem {
+    Event(Event),
+    Completion(Completion),
+}
+
+/// Bounded queue of the events processed by FlowState, applying the OverflowPolicy when full.
+///
+/// Terminate events are never dropped, and are queued even if the queue is full. The same goes
+/// for the completions of the nodes run by the multi-threaded executor, which are returned before
+/// the events.
+pub(crate) struct EventQueue {
+    config: QueueConfig,
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    /// Wakes up the task waiting in pop()
+    #[cfg(feature = "async")]
+    notify: tokio::sync::Notify,
+}
+
+impl EventQueue {
+    pub(crate) fn new(mut config: QueueConfig) -> EventQueue {
+        // Producers waiting for space in a queue of capacity 0 would never be woken up
+        config.capacity = config.capacity.max(1);
+        EventQueue {
+            config,
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                completions: VecDeque::new(),
+                consumer: thread::current().id(),
+                overflows: 0,
+                reported_overflows: 0,
+                last_report: None,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            #[cfg(feature = "async")]
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    pub(crate) fn push(&self, e: Event) -> Result<(), Error> {
+        self.push_event(e, false)
+    }
+
+    /// Queues an event dispatched by FlowState itself. With OverflowPolicy::Block, this never
+    /// waits, as the flow's thread is the one which would make space.
+    pub(crate) fn push_from_flow(&self, e: Event) -> Result<(), Error> {
+        self.push_event(e, true)
+    }
+
+    fn push_event(&self, e: Event, from_flow: bool) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let is_control = matches!(e, Event::Terminate());
+        if state.events.len() >= self.config.capacity && !is_control {
+            state.overflows += 1;
+            match self.config.overflow {
+                OverflowPolicy::Block => {
+                    if !from_flow && state.consumer != thread::current().id() {
+                        while state.events.len() >= self.config.capacity {
+                            state = self.not_full.wait(state).unwrap();
+                        }
+                    }
+                }
+                OverflowPolicy::DropNewest => return Ok(()),
+                OverflowPolicy::DropOldest => {
+                    // Keep the control events, drop the oldest of the others
+                    if let Some(index) = state
+                        .events
+                        .iter()
+                        .position(|e| !matches!(e, Event::Terminate()))
+                    {
+                        state.events.remove(index);
+                    }
+                }
+                OverflowPolicy::Error => {
+                    return Err(Error::QueueFull(self.config.capacity));
+                }
+            }
+        }
+        state.events.push_back(e);
+        self.not_empty.notify_one();
+        #[cfg(feature = "async")]
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    pub(crate) fn push_completion(&self, c: Completion) {
+        self.state.lock().unwrap().completions.push_back(c);
+        self.not_empty.notify_one();
+        #[cfg(feature = "async")]
+        self.notify.notify_one();
+    }
+
+    /// Waits up to `timeout` for the next item. The calling thread becomes the consumer.
+    pub(crate) fn pop_timeout(&self, timeout: Duration) -> Option<QueueItem> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        state.consumer = thread::current().id();
+        loop {
+            if let Some(c) = state.completions.pop_front() {
+                return Some(QueueItem::Completion(c));
+            }
+            if let Some(e) = state.events.pop_front() {
+                self.not_full.notify_one();
+                return Some(QueueItem::Event(e));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = self
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Waits for the next item without blocking the thread. The calling thread becomes the
+    /// consumer.
+    #[cfg(feature = "async")]
+    pub(crate) async fn pop(&self) -> QueueItem {
+        loop {
+            // Created before checking the queue, so that a push in between isn't missed
+            let notified = self.notify.notified();
+            if let Some(item) = self.pop_timeout(Duration::ZERO) {
+                return item;
+            }
+            notified.await;
+        }
+    }
+
+    /// Number of events dispatched while the queue was full.
+    pub(crate) fn overflows(&self) -> u64 {
+        self.state.lock().unwrap().overflows
+    }
+
+    /// Logs the number of overflows since the last report, at most once per
+    /// OVERFLOW_REPORT_INTERVAL.
+    pub(crate) fn report_overflows(&self) {
+        let mut state = self.state.lock().unwrap();
+        let new_overflows = state.overflows - state.reported_overflows;
+        if new_overflows == 0 {
+            return;
+        }
+        if let Some(last_report) = state.last_report {
+            if last_report.elapsed() < OVERFLOW_REPORT_INTERVAL {
+                return;
+            }
+        }
+        warn!(
+            "Event queue full (capacity {}, policy {:?}): {} overflows since the last report, {} in total",
+            self.config.capacity, self.config.overflow, new_overflows, state.overflows
+        );
+        state.reported_overflows = state.overflows;
+        state.last_report = Some(Instant::now());
+    }
+}
+
+impl Debug for EventQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventQueue")
+            .field("config", &self.config)
+            .field("len", &self.state.lock().unwrap().events.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn log_event(text: &str) -> Event {
+        Event::Log(text.to_string())
+    }
+
+    fn pop_log(queue: &EventQueue) -> Option<String> {
+        match queue.pop_timeout(Duration::from_millis(10)) {
+            Some(QueueItem::Event(Event::Log(text))) => Some(text),
+            Some(_) => panic!("unexpected event"),
+            None => None,
+        }
+    }
+
+    fn make_queue(capacity: usize, overflow: OverflowPolicy) -> EventQueue {
+        EventQueue::new(QueueConfig { capacity, overflow })
+    }
+
+    #[test]
+    fn test_queue_drop_newest() {
+        let queue = make_queue(2, OverflowPolicy::DropNewest);
+        for text in ["a", "b", "c"] {
+            queue.push(log_event(text)).unwrap();
+        }
+        assert_eq!(queue.overflows(), 1);
+        assert_eq!(pop_log(&queue).as_deref(), Some("a"));
+        assert_eq!(pop_log(&queue).as_deref(), Some("b"));
+        assert_eq!(pop_log(&queue), None);
+    }
+
+    #[test]
+    fn test_queue_drop_oldest() {
+        let queue = make_queue(2, OverflowPolicy::DropOldest);
+        for text in ["a", "b", "c"] {
+            queue.push(log_event(text)).unwrap();
+        }
+        assert_eq!(pop_log(&queue).as_deref(), Some("b"));
+        assert_eq!(pop_log(&queue).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_queue_error() {
+        let queue = make_queue(1, OverflowPolicy::Error);
+        queue.push(log_event("a")).unwrap();
+        assert!(matches!(
+            queue.push(log_event("b")),
+            Err(Error::QueueFull(1))
+        ));
+        // Terminate isn't subject to the capacity
+        queue.push(Event::Terminate()).unwrap();
+        assert_eq!(queue.overflows(), 1);
+    }
+
+    #[test]
+    fn test_queue_block_before_pop() {
+        // The thread which created the queue runs the flow, even before it waits for events
+        let queue = make_queue(1, OverflowPolicy::Block);
+        queue.push(log_event("a")).unwrap();
+        queue.push(log_event("b")).unwrap();
+        queue.push_from_flow(log_event("c")).unwrap();
+        assert_eq!(queue.overflows(), 2);
+
+        // Nor do events dispatched by the flow from another thread
+        let queue = Arc::new(queue);
+        thread::spawn({
+            let queue = queue.clone();
+            move || queue.push_from_flow(log_event("d")).unwrap()
+        })
+        .join()
+        .unwrap();
+        for text in ["a", "b", "c", "d"] {
+            assert_eq!(pop_log(&queue).as_deref(), Some(text));
+        }
+    }
+
+    #[test]
+    fn test_queue_block() {
+        let queue = Arc::new(make_queue(1, OverflowPolicy::Block));
+        // The consumer thread doesn't block
+        assert_eq!(pop_log(&queue), None);
+        queue.push(log_event("a")).unwrap();
+        queue.push(log_event("b")).unwrap();
+        assert_eq!(pop_log(&queue).as_deref(), Some("a"));
+        assert_eq!(pop_log(&queue).as_deref(), Some("b"));
+
+        // Other threads wait for space
+        queue.push(log_event("c")).unwrap();
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(log_event("d")).unwrap())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+        assert_eq!(pop_log(&queue).as_deref(), Some("c"));
+        producer.join().unwrap();
+        assert_eq!(pop_log(&queue).as_deref(), Some("d"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::common::MessageId;
+use crate::node::{Node, NodeFunctionResult};
+
+/// How FlowState runs the nodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Executor {
+    /// The nodes run one at a time, on the thread calling FlowState::run_once.
+    #[default]
+    SingleThreaded,
+    /// The nodes run concurrently on a pool of `workers` threads.
+    ///
+    /// A node processes one message at a time, unless it is reentrant (see
+    /// Node::reentrant_instance), and the messages sent over a connection arrive in the order they
+    /// were sent.
+    MultiThreaded { workers: usize },
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads running jobs in the order they are submitted.
+pub(crate) struct WorkerPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(workers: usize) -> WorkerPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("notred-worker-{i}"))
+                    .spawn(move || loop {
+                        // The lock is released before running the job
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub(crate) fn execute(&self, job: Job) {
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers once they finish the queued jobs
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Debug for WorkerPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("workers", &self.workers.len())
+            .finish()
+    }
+}
+
+/// Result of running a node on a worker thread, sent back to the thread running the flow.
+#[derive(Debug)]
+pub(crate) struct Completion {
+    pub(crate) node_index: usize,
+    /// Order in which the run was started, among the runs of the same node
+    pub(crate) seq: u64,
+    /// The node, unless the run used a reentrant instance of it
+    pub(crate) node: Option<Box<dyn Node>>,
+    /// Id of the message processed by the run, None if it was run for a timer
+    pub(crate) input_id: Option<MessageId>,
+    /// Err with the panic payload if the node panicked
+    pub(crate) result: Result<NodeFunctionResult, Box<dyn Any + Send>>,
+}
@@ -1,93 +1,489 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use log::*;
 
+use crate::clock::{Clock, NodeClock, SystemClock, TimerId, Timers};
 use crate::common::*;
+use crate::conversion_registry::ConversionRegistry;
 use crate::errors::Error;
-use crate::flow_checker::{check_flow, find_conversions};
+use crate::event_queue::{EventQueue, OverflowPolicy, QueueItem};
+use crate::executor::{Completion, Executor, WorkerPool};
+use crate::flow_checker::{check_flow, find_conversions, infer_types};
 use crate::loader;
-use crate::node::Node;
-use crate::node_util::{node_by_name, node_by_name_mut};
+use crate::message::{
+    ConversionResult, MessageType, DEFAULT_DISPLAY_BINARY_LEN, DEFAULT_MAX_NESTING_DEPTH,
+};
+use crate::node::{Node, NodeCommon, NodeFunctionResult, NodeOutput};
+
+/// How long FlowState::run and run_until wait for an event before checking again whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why FlowState::run, run_until_idle, run_for or run_until returned.
+#[derive(Debug)]
+pub enum StopReason {
+    /// A Terminate event was received, with the reason given by Error::Terminate
+    Terminated(String),
+    /// No events arrived during the quiescence timeout, and no nodes were running
+    Idle,
+    /// The duration given to run_for has elapsed
+    Deadline,
+    /// The predicate given to run_until returned true
+    ConditionMet,
+    Error(Error),
+}
+
+/// Result of FlowState::run, run_until_idle, run_for and run_until.
+#[derive(Debug)]
+pub struct RunSummary {
+    pub reason: StopReason,
+    /// Number of events processed, including the timers fired and the results of the node runs on
+    /// worker threads
+    pub events: u64,
+}
 
 #[derive(Debug)]
 pub struct FlowState {
-    nodes: Vec<Box<dyn Node>>,
+    nodes: Vec<NodeSlot>,
     connections: Vec<Connection>,
-    message_queue_rx: std::sync::mpsc::Receiver<Event>,
-    event_sender: Arc<Mutex<dyn EventSender>>,
+    strict: bool,
+    queue: Arc<EventQueue>,
+    /// None for Executor::SingleThreaded
+    workers: Option<WorkerPool>,
+    clock: Arc<dyn Clock>,
+    timers: Arc<Mutex<Timers>>,
+}
+
+/// What a node is run for.
+// Messages are by far the most common, boxing them would only add allocations
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum Work {
+    Message(MessageTo),
+    Timer(TimerId),
+}
+
+/// A node of the flow, along with what the executor needs to know about it while it runs on a
+/// worker thread.
+#[derive(Debug)]
+struct NodeSlot {
+    common: NodeCommon,
+    num_inputs: usize,
+    num_outputs: usize,
+    /// None while the node runs on a worker thread
+    node: Option<Box<dyn Node>>,
+    /// Messages and timers waiting for the node to finish running
+    pending: VecDeque<Work>,
+    /// Sequence number of the next run to start
+    next_start: u64,
+    /// Sequence number of the next run whose outputs get dispatched
+    next_release: u64,
+    /// Results of the runs which finished before a run started earlier, with the ids of their
+    /// input messages
+    finished: BTreeMap<u64, (Option<MessageId>, NodeFunctionResult)>,
+}
+
+impl Work {
+    fn run(&self, node: &mut dyn Node) -> NodeFunctionResult {
+        match self {
+            Work::Message(mt) => node.run(&mt.message, mt.to.index),
+            Work::Timer(timer) => node.on_timer(*timer),
+        }
+    }
+
+    fn input_id(&self) -> Option<MessageId> {
+        match self {
+            Work::Message(mt) => Some(mt.message.id),
+            Work::Timer(_) => None,
+        }
+    }
+}
+
+impl NodeSlot {
+    fn new(node: Box<dyn Node>) -> NodeSlot {
+        NodeSlot {
+            common: node.common().clone(),
+            num_inputs: node.num_inputs(),
+            num_outputs: node.num_outputs(),
+            node: Some(node),
+            pending: VecDeque::new(),
+            next_start: 0,
+            next_release: 0,
+            finished: BTreeMap::new(),
+        }
+    }
 }
 
+/// EventSender given to the nodes, adding the events to the queue of the flow.
+///
+/// Each node gets its own dispatcher, so that a node thread waiting for space in the queue doesn't
+/// hold up the other nodes.
 #[derive(Debug)]
 pub struct FlowAsyncMessageDispatcher {
-    message_queue_tx: std::sync::mpsc::SyncSender<Event>,
+    queue: Arc<EventQueue>,
 }
 
 impl EventSender for FlowAsyncMessageDispatcher {
-    fn dispatch(&mut self, e: Event) {
-        self.message_queue_tx.send(e).unwrap();
+    fn dispatch(&mut self, e: Event) -> Result<(), Error> {
+        self.queue.push(e)
+    }
+}
+
+/// Builder for FlowState, for the settings provided by the host application rather than the flow
+/// file.
+#[derive(Debug, Default)]
+pub struct FlowStateBuilder {
+    conversions: ConversionRegistry,
+    strict: bool,
+    queue_capacity: Option<usize>,
+    overflow_policy: Option<OverflowPolicy>,
+    executor: Executor,
+    clock: Option<Arc<dyn Clock>>,
+    max_nesting_depth: Option<usize>,
+}
+
+impl FlowStateBuilder {
+    pub fn new() -> FlowStateBuilder {
+        FlowStateBuilder::default()
+    }
+
+    /// Registers a conversion from `src` type to `dst` type, used instead of the built-in one.
+    /// `loss` describes what the conversion may lose, see [`ConversionRegistry::register`].
+    pub fn register_conversion<F>(
+        mut self,
+        src: MessageType,
+        dst: MessageType,
+        loss: Option<&str>,
+        converter: F,
+    ) -> Self
+    where
+        F: Fn(&MessageData, &MessageType) -> ConversionResult + Send + Sync + 'static,
+    {
+        self.conversions.register(src, dst, loss, converter);
+        self
+    }
+
+    /// Registers a conversion for the type pairs matching `predicate`, used instead of the
+    /// built-in one.
+    pub fn register_conversion_if<P, F>(
+        mut self,
+        predicate: P,
+        loss: Option<&str>,
+        converter: F,
+    ) -> Self
+    where
+        P: Fn(&MessageType, &MessageType) -> bool + Send + Sync + 'static,
+        F: Fn(&MessageData, &MessageType) -> ConversionResult + Send + Sync + 'static,
+    {
+        self.conversions.register_if(predicate, loss, converter);
+        self
+    }
+
+    /// Replaces the conversion registry with the given one.
+    pub fn conversions(mut self, registry: ConversionRegistry) -> Self {
+        self.conversions = registry;
+        self
+    }
+
+    /// In a strict flow, lossy implicit conversions (e.g. Float to Int) fail to load, unless the
+    /// connection sets "allow_lossy". Otherwise, they are only logged as warnings.
+    ///
+    /// The flow is also strict if the flow file sets "strict".
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the maximum number of events waiting in the queue of the flow, overriding the
+    /// "capacity" of the "queue" object in the flow file.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what happens to the events dispatched while the queue is full, overriding the
+    /// "overflow" of the "queue" object in the flow file.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = Some(policy);
+        self
+    }
+
+    /// Selects how the nodes are run, Executor::SingleThreaded by default.
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Sets the clock driving the timers of the nodes, SystemClock by default. Tests can use a
+    /// VirtualClock to control the time.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Sets how deep the dictionary and list types of the connections may be nested,
+    /// DEFAULT_MAX_NESTING_DEPTH by default. Deeper types fail to load, so that flow files from
+    /// untrusted sources can't exhaust the stack; this also limits the nesting of the CBOR and
+    /// MessagePack data decoded by the conversions.
+    pub fn max_nesting_depth(mut self, max_depth: usize) -> Self {
+        self.max_nesting_depth = Some(max_depth);
+        self
+    }
+
+    pub fn build(self, text: &str) -> Result<FlowState, Error> {
+        FlowState::from_builder(self, text)
     }
 }
 
 impl FlowState {
     pub fn new(text: &str) -> Result<FlowState, Error> {
-        let (sender, receiver): (
-            std::sync::mpsc::SyncSender<Event>,
-            std::sync::mpsc::Receiver<Event>,
-        ) = std::sync::mpsc::sync_channel(10); // FIXME
-        let event_sender = Arc::new(Mutex::new(FlowAsyncMessageDispatcher {
-            message_queue_tx: sender,
-        }));
+        FlowStateBuilder::new().build(text)
+    }
+
+    pub fn builder() -> FlowStateBuilder {
+        FlowStateBuilder::new()
+    }
 
+    fn from_builder(builder: FlowStateBuilder, text: &str) -> Result<FlowState, Error> {
         let lfd = loader::LoadedFlowDescription::new(text)?;
+        let mut queue_config = lfd.queue.unwrap_or_default();
+        if let Some(capacity) = builder.queue_capacity {
+            queue_config.capacity = capacity;
+        }
+        if let Some(policy) = builder.overflow_policy {
+            queue_config.overflow = policy;
+        }
+        let queue = Arc::new(EventQueue::new(queue_config));
+
+        let clock = builder
+            .clock
+            .unwrap_or_else(|| Arc::new(SystemClock::new()));
+        let timers = Arc::new(Mutex::new(Timers::default()));
+
         let mut nodes = lfd.nodes;
-        for n in &mut nodes {
-            n.create(Some(event_sender.clone()));
+        for (index, n) in nodes.iter_mut().enumerate() {
+            let event_sender = Arc::new(Mutex::new(FlowAsyncMessageDispatcher {
+                queue: queue.clone(),
+            }));
+            n.create(
+                Some(event_sender),
+                NodeClock::new(clock.clone(), timers.clone(), index),
+            );
         }
         let mut connections = lfd.connections;
+        let strict = builder.strict || lfd.strict;
         check_flow(&nodes, &connections)?;
-        find_conversions(&nodes, &mut connections)?;
+        let output_types = infer_types(&nodes, &connections)?;
+        find_conversions(
+            &nodes,
+            &mut connections,
+            &output_types,
+            &builder.conversions,
+            strict,
+            builder
+                .max_nesting_depth
+                .unwrap_or(DEFAULT_MAX_NESTING_DEPTH),
+        )?;
+
+        let workers = match builder.executor {
+            Executor::SingleThreaded => None,
+            Executor::MultiThreaded { workers } => Some(WorkerPool::new(workers)),
+        };
 
         Ok(FlowState {
-            nodes,
+            nodes: nodes.into_iter().map(NodeSlot::new).collect(),
             connections,
-            message_queue_rx: receiver,
-            event_sender: event_sender.clone(),
+            strict,
+            queue,
+            workers,
+            clock,
+            timers,
         })
     }
 
+    /// Adds an event produced while running the flow to the queue.
+    fn dispatch(&self, e: Event) {
+        if let Err(e) = self.queue.push_from_flow(e) {
+            error!("{e}");
+        }
+    }
+
+    fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.common.name == name)
+    }
+
     fn handle_message_to(&mut self, mt: MessageTo) {
-        let dst_node = node_by_name_mut(&mut self.nodes, mt.to.name.as_str()).unwrap();
-        if dst_node.common().log_outputs {
-            if dst_node.num_inputs() == 1 {
-                info!("Input to {}: {}", mt.to.name, mt.message);
+        let index = self.node_index(mt.to.name.as_str()).unwrap();
+        self.schedule(index, Work::Message(mt));
+    }
+
+    /// Fires the earliest timer which has elapsed, if any.
+    fn fire_due_timer(&mut self) -> bool {
+        let now = self.clock.now();
+        let Some((index, timer)) = self.timers.lock().unwrap().pop_due(now) else {
+            return false;
+        };
+        self.schedule(index, Work::Timer(timer));
+        true
+    }
+
+    /// How long to wait for events before the next timer elapses. None if there are no timers,
+    /// or if the clock is virtual and waiting wouldn't make it advance.
+    fn time_until_next_timer(&self) -> Option<Duration> {
+        if self.clock.is_virtual() {
+            return None;
+        }
+        let deadline = self.timers.lock().unwrap().next_deadline()?;
+        Some(deadline.saturating_sub(self.clock.now()))
+    }
+
+    fn schedule(&mut self, index: usize, work: Work) {
+        if self.nodes[index].node.is_none() {
+            self.nodes[index].pending.push_back(work);
+            return;
+        }
+        self.start_run(index, work);
+    }
+
+    /// Runs the node, either right away or on a worker thread. The node must not be running
+    /// already.
+    fn start_run(&mut self, index: usize, work: Work) {
+        let slot = &mut self.nodes[index];
+        if let (Work::Message(mt), true) = (&work, slot.common.log_outputs) {
+            let len = slot
+                .common
+                .log_binary_len
+                .unwrap_or(DEFAULT_DISPLAY_BINARY_LEN);
+            if slot.num_inputs == 1 {
+                info!("Input to {}: {:.len$}", mt.to.name, mt.message);
             } else {
-                info!("Input to {}[{}]: {}", mt.to.name, mt.to.index, mt.message);
+                info!(
+                    "Input to {}[{}]: {:.len$}",
+                    mt.to.name, mt.to.index, mt.message
+                );
             }
         }
-        let node_res = dst_node.run(&mt.message, mt.to.index);
-        if let Ok(Some(msg)) = node_res {
-            self.event_sender
-                .lock()
-                .unwrap()
-                .dispatch(Event::MessageFrom(MessageFrom {
-                    from: NodePort {
-                        name: mt.to.name.clone(),
-                        index: 0,
-                    },
-                    message: msg,
-                }));
+
+        let Some(workers) = &self.workers else {
+            let node = slot.node.as_mut().unwrap();
+            let input_id = work.input_id();
+            let outputs = work.run(node.as_mut());
+            self.dispatch_outputs(index, input_id, outputs);
+            return;
+        };
+
+        let seq = slot.next_start;
+        slot.next_start += 1;
+        // A reentrant node stays available for the next messages, only its instance is sent away.
+        // Timers change the state of the node, so they always run the node itself.
+        let instance = match &work {
+            Work::Message(_) => slot.node.as_ref().unwrap().reentrant_instance(),
+            Work::Timer(_) => None,
+        };
+        let (mut node, returned) = match instance {
+            Some(instance) => (instance, false),
+            None => (slot.node.take().unwrap(), true),
+        };
+        let queue = self.queue.clone();
+        workers.execute(Box::new(move || {
+            let input_id = work.input_id();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| work.run(node.as_mut())));
+            queue.push_completion(Completion {
+                node_index: index,
+                seq,
+                node: returned.then_some(node),
+                input_id,
+                result,
+            });
+        }));
+    }
+
+    fn handle_completion(&mut self, c: Completion) {
+        let index = c.node_index;
+        let slot = &mut self.nodes[index];
+        if c.node.is_some() {
+            slot.node = c.node;
+        }
+        match c.result {
+            Ok(outputs) => {
+                slot.finished.insert(c.seq, (c.input_id, outputs));
+            }
+            // Same as if the node had panicked on the thread running the flow
+            Err(payload) => panic::resume_unwind(payload),
+        }
+
+        // Dispatch the outputs in the order the runs were started, so that a reentrant node
+        // keeps the order of the messages
+        loop {
+            let slot = &mut self.nodes[index];
+            let Some((input_id, outputs)) = slot.finished.remove(&slot.next_release) else {
+                break;
+            };
+            slot.next_release += 1;
+            self.dispatch_outputs(index, input_id, outputs);
+        }
+
+        let slot = &mut self.nodes[index];
+        if slot.node.is_some() {
+            if let Some(work) = slot.pending.pop_front() {
+                self.start_run(index, work);
+            }
+        }
+    }
+
+    fn dispatch_outputs(
+        &self,
+        index: usize,
+        input_id: Option<MessageId>,
+        outputs: NodeFunctionResult,
+    ) {
+        let slot = &self.nodes[index];
+        let outputs = match outputs {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                error!("Error in {}: {e}", slot.common.name);
+                return;
+            }
+        };
+        for NodeOutput { index, message } in outputs {
+            if index >= slot.num_outputs {
+                error!(
+                    "{}",
+                    Error::InvalidPortIndex(slot.common.name.clone(), index)
+                );
+                continue;
+            }
+            // Nodes may output the message they received, or the same message several times
+            let mut message = message.with_new_id();
+            if message.parent_id.is_none() {
+                message.parent_id = input_id;
+            }
+            self.dispatch(Event::MessageFrom(MessageFrom {
+                from: NodePort {
+                    name: slot.common.name.clone(),
+                    index,
+                },
+                message,
+            }));
         }
     }
 
     fn handle_message_from(&mut self, mf: MessageFrom) {
-        if let Some(src_node) = node_by_name_mut(&mut self.nodes, mf.from.name.as_str()) {
-            if src_node.common().log_outputs {
-                if src_node.num_outputs() == 1 {
-                    info!("Output from {}: {}", mf.from.name, mf.message)
+        if let Some(index) = self.node_index(mf.from.name.as_str()) {
+            let src_node = &self.nodes[index];
+            if src_node.common.log_outputs {
+                let len = src_node
+                    .common
+                    .log_binary_len
+                    .unwrap_or(DEFAULT_DISPLAY_BINARY_LEN);
+                if src_node.num_outputs == 1 {
+                    info!("Output from {}: {:.len$}", mf.from.name, mf.message)
                 } else {
                     info!(
-                        "Output from {}[{}]: {}",
+                        "Output from {}[{}]: {:.len$}",
                         mf.from.name, mf.from.index, mf.message
                     )
                 }
@@ -99,21 +495,19 @@ impl FlowState {
                 continue;
             }
 
-            match c.conversion.unwrap()(&mf.message, &c.dest_type.clone().unwrap()) {
-                Ok(converted_message) => {
-                    self.event_sender
-                        .lock()
-                        .unwrap()
-                        .dispatch(Event::MessageTo(MessageTo {
-                            message: converted_message,
-                            to: c.dest.clone(),
-                        }))
-                }
-                Err(e) => self
-                    .event_sender
-                    .lock()
-                    .unwrap()
-                    .dispatch(Event::Log(e.to_string())),
+            match c.conversion.as_ref().unwrap()(&mf.message.data, c.dest_type.as_ref().unwrap()) {
+                Ok(converted_data) => self.dispatch(Event::MessageTo(MessageTo {
+                    message: Message {
+                        data: converted_data,
+                        ..mf.message.clone()
+                    }
+                    .with_new_id(),
+                    to: c.dest.clone(),
+                })),
+                Err(e) => self.dispatch(Event::Log(format!(
+                    "Connection {}[{}] -> {}[{}]: {e}",
+                    c.source.name, c.source.index, c.dest.name, c.dest.index
+                ))),
             }
         }
     }
@@ -122,8 +516,153 @@ impl FlowState {
         info!("{log_msg}");
     }
 
+    /// Processes one event, or fires one timer which has elapsed. Waits up to `timeout` for
+    /// either, and returns Error::Timeout if there was none.
     pub fn run_once(&mut self, timeout: Duration) -> Result<(), Error> {
-        let e = self.message_queue_rx.recv_timeout(timeout)?;
+        self.queue.report_overflows();
+        if self.fire_due_timer() {
+            return Ok(());
+        }
+        let timeout = match self.time_until_next_timer() {
+            Some(until_timer) => timeout.min(until_timer),
+            None => timeout,
+        };
+        match self.queue.pop_timeout(timeout) {
+            Some(item) => self.handle_item(item),
+            None if self.fire_due_timer() => Ok(()),
+            None => Err(Error::Timeout(RecvTimeoutError::Timeout)),
+        }
+    }
+
+    /// Processes the events until the flow terminates or fails.
+    pub fn run(&mut self) -> RunSummary {
+        self.run_loop(None, None, |_| false)
+    }
+
+    /// Processes the events until none arrive for `quiescence_timeout` while no nodes are running,
+    /// or until the flow terminates or fails.
+    pub fn run_until_idle(&mut self, quiescence_timeout: Duration) -> RunSummary {
+        self.run_loop(None, Some(quiescence_timeout), |_| false)
+    }
+
+    /// Processes the events for `duration` of the flow's clock, unless the flow terminates or
+    /// fails before.
+    ///
+    /// With a VirtualClock, the clock is advanced by this function instead of waiting: once the
+    /// pending events are processed, it moves to the deadline of the next timer, up to `duration`
+    /// later. The timers are fired on the calling thread, as usual.
+    pub fn run_for(&mut self, duration: Duration) -> RunSummary {
+        self.run_loop(Some(self.clock.now() + duration), None, |_| false)
+    }
+
+    /// Processes the events until `predicate` returns true, or until the flow terminates or fails.
+    /// The predicate is checked before processing each event, and at least every POLL_INTERVAL.
+    pub fn run_until<P>(&mut self, predicate: P) -> RunSummary
+    where
+        P: FnMut(&FlowState) -> bool,
+    {
+        self.run_loop(None, None, predicate)
+    }
+
+    /// `deadline` is a time of the flow's clock. A virtual clock is advanced up to it.
+    fn run_loop<P>(
+        &mut self,
+        deadline: Option<Duration>,
+        quiescence_timeout: Option<Duration>,
+        mut predicate: P,
+    ) -> RunSummary
+    where
+        P: FnMut(&FlowState) -> bool,
+    {
+        let mut events = 0;
+        let reason = loop {
+            if predicate(self) {
+                break StopReason::ConditionMet;
+            }
+            let mut timeout = quiescence_timeout.unwrap_or(POLL_INTERVAL);
+            match deadline {
+                // Waiting doesn't make a virtual clock advance, only waiting for running nodes
+                Some(_) if self.clock.is_virtual() && self.runs_in_progress() == 0 => {
+                    timeout = Duration::ZERO;
+                }
+                Some(_) if self.clock.is_virtual() => {}
+                Some(deadline) => {
+                    let now = self.clock.now();
+                    if now >= deadline {
+                        break StopReason::Deadline;
+                    }
+                    timeout = timeout.min(deadline - now);
+                }
+                None => {}
+            }
+            match self.run_once(timeout) {
+                Ok(()) => events += 1,
+                Err(Error::Timeout(_)) => {
+                    if quiescence_timeout.is_some() && self.runs_in_progress() == 0 {
+                        break StopReason::Idle;
+                    }
+                    if let Some(deadline) = deadline {
+                        if self.clock.is_virtual() && self.runs_in_progress() == 0 {
+                            if self.clock.now() >= deadline {
+                                break StopReason::Deadline;
+                            }
+                            let next_timer = self.timers.lock().unwrap().next_deadline();
+                            self.clock
+                                .advance_to(next_timer.map_or(deadline, |t| t.min(deadline)));
+                        }
+                    }
+                }
+                Err(Error::Terminate(reason)) => {
+                    events += 1;
+                    break StopReason::Terminated(reason);
+                }
+                Err(e) => break StopReason::Error(e),
+            }
+        };
+        RunSummary { reason, events }
+    }
+
+    /// Processes the events until the flow terminates, waiting for them without blocking the
+    /// thread. Returns Ok once a Terminate event is received.
+    ///
+    /// The timers of the nodes (e.g. "ticker") are driven by the Tokio runtime. With a TokioClock
+    /// they follow the time of the runtime, including its paused time in tests. A VirtualClock
+    /// doesn't wake up this function when advanced, only new events do.
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) -> Result<(), Error> {
+        loop {
+            self.queue.report_overflows();
+            if self.fire_due_timer() {
+                tokio::task::yield_now().await;
+                continue;
+            }
+            let item = match self.time_until_next_timer() {
+                Some(until_timer) => {
+                    match tokio::time::timeout(until_timer, self.queue.pop()).await {
+                        Ok(item) => item,
+                        Err(_) => continue,
+                    }
+                }
+                None => self.queue.pop().await,
+            };
+            match self.handle_item(item) {
+                Ok(()) => {}
+                Err(Error::Terminate(_)) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            // Let the other tasks (e.g. the nodes) run while there are more events in the queue
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn handle_item(&mut self, item: QueueItem) -> Result<(), Error> {
+        let e = match item {
+            QueueItem::Event(e) => e,
+            QueueItem::Completion(c) => {
+                self.handle_completion(c);
+                return Ok(());
+            }
+        };
 
         match e {
             Event::MessageTo(mt) => {
@@ -143,21 +682,239 @@ impl FlowState {
         Ok(())
     }
 
-    pub fn get_node_by_name(&self, name: &str) -> Option<&Box<dyn Node>> {
-        return node_by_name(&self.nodes, name);
+    /// Number of events dispatched while the event queue was full, see OverflowPolicy.
+    pub fn queue_overflows(&self) -> u64 {
+        self.queue.overflows()
+    }
+
+    /// Whether lossy implicit conversions are rejected, see FlowStateBuilder::strict.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Number of node runs started on the worker threads whose outputs haven't been dispatched
+    /// yet, including the messages waiting for their node to finish running. Always 0 with
+    /// Executor::SingleThreaded.
+    pub fn runs_in_progress(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|n| (n.next_start - n.next_release) as usize + n.pending.len())
+            .sum()
+    }
+
+    /// Returns None if there is no such node, or if it is running on a worker thread.
+    pub fn get_node_by_name(&self, name: &str) -> Option<&dyn Node> {
+        let index = self.node_index(name)?;
+        self.nodes[index].node.as_deref()
     }
 
+    /// Returns None if there is no such node, or if it is running on a worker thread.
     pub fn get_node_by_name_mut(&mut self, name: &str) -> Option<&mut Box<dyn Node>> {
-        return node_by_name_mut(&mut self.nodes, name);
+        let index = self.node_index(name)?;
+        self.nodes[index].node.as_mut()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::node::{NodeCommon, NodeFunctionResult};
     use crate::nodes::capture::CaptureNode;
+    use crate::{ConversionError, VirtualClock};
 
     use super::*;
 
+    /// Emits "second" on output 1, then "first" and "third" on output 0.
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestFanoutNode {
+        #[serde(flatten)]
+        common: NodeCommon,
+    }
+
+    #[typetag::serde(name = "test_fanout")]
+    impl Node for TestFanoutNode {
+        fn common(&self) -> &NodeCommon {
+            &self.common
+        }
+
+        fn create(
+            &mut self,
+            _event_sender: Option<Arc<Mutex<dyn EventSender>>>,
+            _clock: NodeClock,
+        ) {
+        }
+
+        fn run(&mut self, _msg: &Message, _index: usize) -> NodeFunctionResult {
+            Ok(vec![
+                NodeOutput::new(1, Message::new(MessageData::from_str("second"))),
+                NodeOutput::new(0, Message::new(MessageData::from_str("first"))),
+                NodeOutput::new(0, Message::new(MessageData::from_str("third"))),
+            ])
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn num_inputs(&self) -> usize {
+            1
+        }
+
+        fn num_outputs(&self) -> usize {
+            2
+        }
+
+        fn input_type(&self, _index: usize) -> Option<&MessageType> {
+            None
+        }
+
+        fn output_type(&self, _index: usize) -> &MessageType {
+            static OUTPUT_TYPE: MessageType = MessageType::Text(crate::TextContentType::Plain);
+            &OUTPUT_TYPE
+        }
+    }
+
+    /// Forwards the messages unchanged; the output has the type of the input.
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestPassthroughNode {
+        #[serde(flatten)]
+        common: NodeCommon,
+    }
+
+    #[typetag::serde(name = "test_passthrough")]
+    impl Node for TestPassthroughNode {
+        fn common(&self) -> &NodeCommon {
+            &self.common
+        }
+
+        fn create(
+            &mut self,
+            _event_sender: Option<Arc<Mutex<dyn EventSender>>>,
+            _clock: NodeClock,
+        ) {
+        }
+
+        fn run(&mut self, msg: &Message, _index: usize) -> NodeFunctionResult {
+            Ok(vec![NodeOutput::new(0, msg.clone())])
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn num_inputs(&self) -> usize {
+            1
+        }
+
+        fn num_outputs(&self) -> usize {
+            1
+        }
+
+        fn input_type(&self, _index: usize) -> Option<&MessageType> {
+            None
+        }
+
+        fn output_type(&self, _index: usize) -> &MessageType {
+            unreachable!("output type is inferred from the input type")
+        }
+
+        fn infer_output_type(
+            &self,
+            _index: usize,
+            input_types: &[Option<MessageType>],
+        ) -> Option<MessageType> {
+            input_types[0].clone()
+        }
+    }
+
+    /// Forwards text messages holding a number after sleeping for (number % 4) ms, and records
+    /// the order of the messages and the number of concurrent runs.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct TestSequenceNode {
+        #[serde(flatten)]
+        common: NodeCommon,
+        #[serde(default)]
+        reentrant: bool,
+        /// Inputs seen by this instance
+        #[serde(skip)]
+        inputs: Vec<String>,
+        #[serde(skip)]
+        active_runs: Arc<AtomicUsize>,
+        #[serde(skip)]
+        max_active_runs: Arc<AtomicUsize>,
+    }
+
+    #[typetag::serde(name = "test_sequence")]
+    impl Node for TestSequenceNode {
+        fn common(&self) -> &NodeCommon {
+            &self.common
+        }
+
+        fn create(
+            &mut self,
+            _event_sender: Option<Arc<Mutex<dyn EventSender>>>,
+            _clock: NodeClock,
+        ) {
+        }
+
+        fn run(&mut self, msg: &Message, _index: usize) -> NodeFunctionResult {
+            let active = self.active_runs.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active_runs.fetch_max(active, Ordering::SeqCst);
+            let text = msg.data.as_text().unwrap().clone();
+            thread::sleep(Duration::from_millis(text.parse::<u64>().unwrap() % 4));
+            self.inputs.push(text);
+            self.active_runs.fetch_sub(1, Ordering::SeqCst);
+            Ok(vec![NodeOutput::new(0, msg.clone())])
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn num_inputs(&self) -> usize {
+            1
+        }
+
+        fn num_outputs(&self) -> usize {
+            1
+        }
+
+        fn input_type(&self, _index: usize) -> Option<&MessageType> {
+            static INPUT_TYPE: MessageType = MessageType::Text(crate::TextContentType::Plain);
+            Some(&INPUT_TYPE)
+        }
+
+        fn output_type(&self, _index: usize) -> &MessageType {
+            static OUTPUT_TYPE: MessageType = MessageType::Text(crate::TextContentType::Plain);
+            &OUTPUT_TYPE
+        }
+
+        fn reentrant_instance(&self) -> Option<Box<dyn Node>> {
+            if self.reentrant {
+                Some(Box::new(self.clone()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn captured_data<'a>(flow: &'a FlowState, name: &str) -> Vec<&'a MessageData> {
+        flow.get_node_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<CaptureNode>()
+            .unwrap()
+            .get_captured_messages()
+            .iter()
+            .map(|m| &m.data)
+            .collect()
+    }
+
     #[test]
     fn test_create_flow() {
         let json_str = r#"
@@ -180,26 +937,931 @@ mod test {
         assert_eq!(flow.connections.len(), 4);
         assert_eq!(flow.nodes.len(), 4);
 
-        for _i in 1..10 {
-            let res = flow.run_once(Duration::from_millis(100));
-            if let Ok(()) = res {
-                continue;
-            }
-            if let Err(Error::Timeout(_)) = res {
-                continue;
-            }
-            res.unwrap();
-        }
+        // The timer and tick of ticker1, 3 inputs and 3 outputs of the append nodes, 2 inputs of
+        // capture1
+        assert_eq!(run_until_idle(&mut flow), 10);
 
-        let capture_node = flow
-            .get_node_by_name("capture1")
-            .unwrap()
-            .as_any()
-            .downcast_ref::<CaptureNode>()
-            .unwrap();
-        let msgs = capture_node.get_captured_messages();
+        let msgs = captured_data(&flow, "capture1");
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs.contains(&&MessageData::from_str("0 test2")));
+        assert!(msgs.contains(&&MessageData::from_str("0 test test2")));
+    }
+
+    fn run_until_idle(flow: &mut FlowState) -> u64 {
+        let summary = flow.run_until_idle(Duration::from_millis(100));
+        assert!(matches!(summary.reason, StopReason::Idle), "{summary:?}");
+        summary.events
+    }
+
+    #[test]
+    fn test_message_envelope() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "test_passthrough", "name":"pass1"},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "capture", "name":"capture2"}
+                ],
+                "connections": [
+                    {"source": {"name":"append1"}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"append1"}, "dest": {"name": "pass1"}},
+                    {"source": {"name":"pass1"}, "dest": {"name": "capture2"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+
+        let input = Message::new(MessageData::from_str("input")).with_topic("topic1");
+        flow.dispatch(Event::MessageTo(MessageTo {
+            message: input.clone(),
+            to: NodePort {
+                name: "append1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+        let output = Message::new(MessageData::from_str("output"))
+            .with_topic("topic2")
+            .with_header("key", "value");
+        flow.dispatch(Event::MessageFrom(MessageFrom {
+            message: output.clone(),
+            from: NodePort {
+                name: "append1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+
+        let captured = |name| {
+            flow.get_node_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<CaptureNode>()
+                .unwrap()
+                .get_captured_messages()
+                .clone()
+        };
+        let msgs = captured("capture1");
+        let passed = captured("capture2");
         assert_eq!(msgs.len(), 2);
-        assert!(msgs.contains(&Message::from_str("0 test2")));
-        assert!(msgs.contains(&Message::from_str("0 test test2")));
+        assert_eq!(passed.len(), 2);
+        // Output of a node refers to the input it was produced from
+        assert_ne!(msgs[0].id, input.id);
+        assert_eq!(msgs[0].parent_id, Some(input.id));
+        assert_eq!(msgs[0].data, MessageData::from_str("input test"));
+        // Envelope is preserved by the connection, but each destination gets its own copy
+        assert_ne!(msgs[1].id, output.id);
+        assert_eq!(
+            Message {
+                id: output.id,
+                ..msgs[1].clone()
+            },
+            output
+        );
+        // A node passing its input on sends a new message, produced from the input
+        assert_ne!(passed[1].id, msgs[1].id);
+        assert_eq!(passed[1].data, output.data);
+        assert_eq!(passed[1].topic, output.topic);
+        assert!(passed[1].parent_id.is_some());
+        // Fan-out doesn't copy one id to several destinations
+        assert_ne!(passed[1].parent_id, Some(msgs[1].id));
+        let mut ids: Vec<_> = passed.iter().chain(msgs.iter()).map(|m| m.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+    }
+
+    #[test]
+    fn test_message_parent_set_by_node() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "capture", "name":"capture2"}
+                ],
+                "connections": [
+                    {"source": {"name":"append1"}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"append1"}, "dest": {"name": "capture2"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+
+        let input = Message::new(MessageData::from_str("input"));
+        let other = Message::new(MessageData::from_str("other"));
+        let mut output = Message::new(MessageData::from_str("output"));
+        output.parent_id = Some(other.id);
+        let index = flow.node_index("append1").unwrap();
+        flow.dispatch_outputs(
+            index,
+            Some(input.id),
+            Ok(vec![
+                NodeOutput::new(0, output.clone()),
+                NodeOutput::new(0, Message::new(MessageData::from_str("derived"))),
+            ]),
+        );
+        run_until_idle(&mut flow);
+
+        let captured = |name| {
+            flow.get_node_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<CaptureNode>()
+                .unwrap()
+                .get_captured_messages()
+                .clone()
+        };
+        let (msgs1, msgs2) = (captured("capture1"), captured("capture2"));
+        assert_eq!(msgs1.len(), 2);
+        assert_eq!(msgs2.len(), 2);
+        for msgs in [&msgs1, &msgs2] {
+            // The parent set by the node is kept, the other output refers to the input
+            assert_eq!(msgs[0].parent_id, Some(other.id));
+            assert_eq!(msgs[1].parent_id, Some(input.id));
+        }
+        // Each output still gets a new id for each destination
+        let mut ids: Vec<_> = msgs1.iter().chain(msgs2.iter()).map(|m| m.id).collect();
+        ids.push(output.id);
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+    }
+
+    #[test]
+    fn test_multiple_outputs() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "test_fanout", "name":"fanout1"},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "capture", "name":"capture2"}
+                ],
+                "connections": [
+                    {"source": {"name":"fanout1", "index": 0}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"fanout1", "index": 1}, "dest": {"name": "capture2"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+
+        flow.dispatch(Event::MessageTo(MessageTo {
+            message: Message::new(MessageData::Int(0)),
+            to: NodePort {
+                name: "fanout1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![
+                &MessageData::from_str("first"),
+                &MessageData::from_str("third")
+            ]
+        );
+        assert_eq!(
+            captured_data(&flow, "capture2"),
+            vec![&MessageData::from_str("second")]
+        );
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 300},
+                    {"class": "ticker", "name":"ticker2", "period": 500, "limit": 2},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"ticker2"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        let clock = VirtualClock::new();
+        let mut flow = FlowState::builder()
+            .clock(clock.clone())
+            .build(json_str)
+            .unwrap();
+        let mut run = |advance_ms| {
+            clock.advance(Duration::from_millis(advance_ms));
+            let summary = flow.run_until_idle(Duration::ZERO);
+            assert!(matches!(summary.reason, StopReason::Idle));
+            captured_data(&flow, "capture1")
+                .iter()
+                .map(|data| match data {
+                    MessageData::Int(i) => *i,
+                    _ => panic!("unexpected data {data}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert!(run(299).is_empty());
+        assert_eq!(run(1), vec![0]);
+        // Ticks at 500 (ticker2), 600, 900, 1000 (ticker2)
+        assert_eq!(run(700), vec![0, 0, 1, 2, 1]);
+        // ticker2 has reached its limit
+        assert_eq!(run(500), vec![0, 0, 1, 2, 1, 3, 4]);
+
+        // run_for() advances the virtual clock itself, firing the timers up to the deadline
+        let summary = flow.run_for(Duration::from_millis(900));
+        assert!(matches!(summary.reason, StopReason::Deadline));
+        assert_eq!(clock.now(), Duration::from_millis(2400));
+        assert_eq!(captured_data(&flow, "capture1").len(), 10);
+        assert_eq!(
+            captured_data(&flow, "capture1")[7..],
+            [
+                &MessageData::Int(5),
+                &MessageData::Int(6),
+                &MessageData::Int(7)
+            ]
+        );
+        // Nothing is due until the deadline
+        let summary = flow.run_for(Duration::from_millis(200));
+        assert!(matches!(summary.reason, StopReason::Deadline));
+        assert_eq!(clock.now(), Duration::from_millis(2600));
+        assert_eq!(captured_data(&flow, "capture1").len(), 10);
+    }
+
+    #[test]
+    fn test_run_helpers() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 10},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "terminate", "name":"terminate1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+
+        let summary = flow.run_until(|flow| captured_data(flow, "capture1").len() == 3);
+        assert!(matches!(summary.reason, StopReason::ConditionMet));
+        // At least the ticks and their copies to capture1
+        assert!(summary.events >= 6);
+
+        let summary = flow.run_for(Duration::from_millis(50));
+        assert!(matches!(summary.reason, StopReason::Deadline));
+        assert!(captured_data(&flow, "capture1").len() > 3);
+
+        // The ticker keeps the flow busy
+        flow.dispatch(Event::MessageTo(MessageTo {
+            message: Message::new(MessageData::Null),
+            to: NodePort {
+                name: "terminate1".to_string(),
+                index: 0,
+            },
+        }));
+        let summary = flow.run();
+        assert!(matches!(summary.reason, StopReason::Terminated(_)));
+        assert!(summary.events >= 2);
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "test_fanout", "name":"fanout1"},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "capture", "name":"capture2"}
+                ],
+                "connections": [
+                    {"source": {"name":"fanout1", "index": 0}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"fanout1", "index": 1}, "dest": {"name": "capture2"}}
+                ],
+                "queue": {"capacity": 1, "overflow": "drop_newest"}
+            }"#;
+        let input = || {
+            Event::MessageTo(MessageTo {
+                message: Message::new(MessageData::Int(0)),
+                to: NodePort {
+                    name: "fanout1".to_string(),
+                    index: 0,
+                },
+            })
+        };
+
+        // Only the first output of the node fits into the queue
+        let mut flow = FlowState::new(json_str).unwrap();
+        flow.dispatch(input());
+        run_until_idle(&mut flow);
+        assert!(captured_data(&flow, "capture1").is_empty());
+        assert_eq!(
+            captured_data(&flow, "capture2"),
+            vec![&MessageData::from_str("second")]
+        );
+        assert_eq!(flow.queue_overflows(), 2);
+
+        // The outputs of the node don't wait for space in the queue, as nothing else would make it,
+        // and neither do the events dispatched before running the flow
+        let mut flow = FlowState::builder()
+            .overflow_policy(OverflowPolicy::Block)
+            .build(json_str)
+            .unwrap();
+        flow.dispatch(input());
+        flow.dispatch(input());
+        run_until_idle(&mut flow);
+        assert_eq!(captured_data(&flow, "capture1").len(), 4);
+        assert_eq!(captured_data(&flow, "capture2").len(), 2);
+    }
+
+    fn check_ordering(executor: Executor) {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "test_sequence", "name":"serial1"},
+                    {"class": "test_sequence", "name":"reentrant1", "reentrant": true},
+                    {"class": "append", "name":"append1", "what_to_append":""},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "capture", "name":"capture2"},
+                    {"class": "capture", "name":"capture3"}
+                ],
+                "connections": [
+                    {"source": {"name":"serial1"}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"serial1"}, "dest": {"name": "append1"}},
+                    {"source": {"name":"append1"}, "dest": {"name": "capture2"}},
+                    {"source": {"name":"reentrant1"}, "dest": {"name": "capture3"}}
+                ],
+                "queue": {"capacity": 1000}
+            }"#;
+        let mut flow = FlowState::builder()
+            .executor(executor)
+            .build(json_str)
+            .unwrap();
+        let expected: Vec<MessageData> = (0..100)
+            .map(|i| MessageData::from_string(&i.to_string()))
+            .collect();
+        for data in &expected {
+            for name in ["serial1", "reentrant1"] {
+                flow.dispatch(Event::MessageTo(MessageTo {
+                    message: Message::new(data.clone()),
+                    to: NodePort {
+                        name: name.to_string(),
+                        index: 0,
+                    },
+                }));
+            }
+        }
+        run_until_idle(&mut flow);
+
+        let expected: Vec<&MessageData> = expected.iter().collect();
+        for name in ["capture1", "capture2", "capture3"] {
+            assert_eq!(captured_data(&flow, name), expected, "{name}");
+        }
+        let sequence_node = |name: &str| -> TestSequenceNode {
+            flow.get_node_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<TestSequenceNode>()
+                .unwrap()
+                .clone()
+        };
+        let serial1 = sequence_node("serial1");
+        assert_eq!(serial1.inputs.len(), 100);
+        assert!(serial1
+            .inputs
+            .iter()
+            .enumerate()
+            .all(|(i, text)| *text == i.to_string()));
+        assert_eq!(serial1.max_active_runs.load(Ordering::SeqCst), 1);
+
+        let max_reentrant_runs = sequence_node("reentrant1")
+            .max_active_runs
+            .load(Ordering::SeqCst);
+        match executor {
+            Executor::SingleThreaded => assert_eq!(max_reentrant_runs, 1),
+            Executor::MultiThreaded { .. } => assert!(max_reentrant_runs > 1),
+        }
+    }
+
+    #[test]
+    fn test_single_threaded_ordering() {
+        check_ordering(Executor::SingleThreaded);
+    }
+
+    #[test]
+    fn test_multi_threaded_ordering() {
+        check_ordering(Executor::MultiThreaded { workers: 4 });
+    }
+
+    #[test]
+    fn test_multi_threaded_concurrency() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "test_sequence", "name":"serial1"},
+                    {"class": "test_sequence", "name":"serial2"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"serial1"}, "dest": {"name": "capture1"}},
+                    {"source": {"name":"serial2"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        let mut flow = FlowState::builder()
+            .executor(Executor::MultiThreaded { workers: 2 })
+            .build(json_str)
+            .unwrap();
+        // Both nodes count their runs together
+        let active_runs = Arc::new(AtomicUsize::new(0));
+        let max_active_runs = Arc::new(AtomicUsize::new(0));
+        for name in ["serial1", "serial2"] {
+            *flow.get_node_by_name_mut(name).unwrap() = Box::new(TestSequenceNode {
+                common: NodeCommon::new(name),
+                reentrant: false,
+                inputs: Vec::new(),
+                active_runs: active_runs.clone(),
+                max_active_runs: max_active_runs.clone(),
+            });
+        }
+        for _ in 0..10 {
+            for name in ["serial1", "serial2"] {
+                flow.dispatch(Event::MessageTo(MessageTo {
+                    message: Message::new(MessageData::from_str("3")),
+                    to: NodePort {
+                        name: name.to_string(),
+                        index: 0,
+                    },
+                }));
+            }
+        }
+        run_until_idle(&mut flow);
+        assert_eq!(captured_data(&flow, "capture1").len(), 20);
+        // The slow nodes don't wait for each other
+        assert_eq!(max_active_runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_declared_connection_type() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"append1"}, "dest": {"name": "capture1"}, "type": "integer"}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+        flow.dispatch(Event::MessageFrom(MessageFrom {
+            message: Message::new(MessageData::from_str("42")),
+            from: NodePort {
+                name: "append1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![&MessageData::Int(42)]
+        );
+
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "append", "name":"append2", "what_to_append":" test"}
+                ],
+                "connections": [
+                    {"source": {"name":"append1"}, "dest": {"name": "append2"}, "type": "integer"}
+                ]
+            }"#;
+        assert!(matches!(
+            FlowState::new(json_str),
+            Err(Error::ConversionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_explicit_conversion() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"append1"}, "dest": {"name": "capture1"},
+                     "type": "binary/unknown", "convert": {"name": "hex"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+        flow.dispatch(Event::MessageFrom(MessageFrom {
+            message: Message::new(MessageData::from_str("0102")),
+            from: NodePort {
+                name: "append1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![&MessageData::Binary(crate::Binary {
+                value: vec![1, 2],
+                content_type: crate::BinaryContentType::Unknown
+            })]
+        );
+    }
+
+    #[test]
+    fn test_explicit_conversion_invalid() {
+        for connection in [
+            // conversion doesn't apply to text -> text
+            r#"{"source": {"name":"append1"}, "dest": {"name": "append2"}, "convert": {"name": "hex"}}"#,
+            // unknown conversion
+            r#"{"source": {"name":"append1"}, "dest": {"name": "capture1"}, "type": "binary/unknown", "convert": {"name": "rot13"}}"#,
+            // destination type unknown
+            r#"{"source": {"name":"append1"}, "dest": {"name": "capture1"}, "convert": {"name": "utf8"}}"#,
+        ] {
+            let json_str = format!(
+                r#"
+                {{
+                    "nodes": [
+                        {{"class": "append", "name":"append1", "what_to_append":" test"}},
+                        {{"class": "append", "name":"append2", "what_to_append":" test"}},
+                        {{"class": "capture", "name":"capture1"}}
+                    ],
+                    "connections": [{connection}]
+                }}"#
+            );
+            assert!(matches!(
+                FlowState::new(&json_str),
+                Err(Error::ConversionError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_registered_conversion() {
+        let flow_text = |strict: bool| {
+            format!(
+                r#"
+                {{
+                    "nodes": [
+                        {{"class": "append", "name":"append1", "what_to_append":" test"}},
+                        {{"class": "capture", "name":"capture1"}}
+                    ],
+                    "connections": [
+                        {{"source": {{"name":"append1"}}, "dest": {{"name": "capture1"}}, "type": "integer"}}
+                    ],
+                    "strict": {strict}
+                }}"#
+            )
+        };
+        let builder = |loss| {
+            FlowState::builder().register_conversion(
+                MessageType::Text(crate::TextContentType::Plain),
+                MessageType::Int,
+                loss,
+                |src, dst| match src.as_text() {
+                    Some(text) => Ok(MessageData::Int(text.len() as i64)),
+                    None => Err(ConversionError::new(src, dst, "not a text")),
+                },
+            )
+        };
+        let mut flow = builder(None).build(&flow_text(false)).unwrap();
+        flow.dispatch(Event::MessageFrom(MessageFrom {
+            message: Message::new(MessageData::from_str("hello")),
+            from: NodePort {
+                name: "append1".to_string(),
+                index: 0,
+            },
+        }));
+        run_until_idle(&mut flow);
+        assert_eq!(captured_data(&flow, "capture1"), vec![&MessageData::Int(5)]);
+
+        // A lossy registered conversion is rejected by strict flows, like a built-in one
+        assert!(builder(None).build(&flow_text(true)).is_ok());
+        assert!(builder(Some("only the length of the text is kept"))
+            .build(&flow_text(false))
+            .is_ok());
+        match builder(Some("only the length of the text is kept")).build(&flow_text(true)) {
+            Err(Error::ConversionError(e)) => {
+                assert!(e.contains("only the length of the text is kept"), "{e}")
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn test_strict() {
+        let flow_text = |strict: bool, allow_lossy: bool| {
+            format!(
+                r#"
+                {{
+                    "nodes": [
+                        {{"class": "ticker", "name":"ticker1", "period": 50, "limit":1}},
+                        {{"class": "capture", "name":"capture1"}}
+                    ],
+                    "connections": [
+                        {{"source": {{"name":"ticker1"}}, "dest": {{"name": "capture1"}}, "type": "float", "allow_lossy": {allow_lossy}}}
+                    ],
+                    "strict": {strict}
+                }}"#
+            )
+        };
+        // Int -> Float is lossy: only a warning by default
+        let flow = FlowState::new(&flow_text(false, false)).unwrap();
+        assert!(!flow.is_strict());
+
+        assert!(matches!(
+            FlowState::new(&flow_text(true, false)),
+            Err(Error::ConversionError(_))
+        ));
+        assert!(matches!(
+            FlowState::builder()
+                .strict(true)
+                .build(&flow_text(false, false)),
+            Err(Error::ConversionError(_))
+        ));
+
+        let flow = FlowState::new(&flow_text(true, true)).unwrap();
+        assert!(flow.is_strict());
+    }
+
+    #[test]
+    fn test_strict_nested_types() {
+        let flow_text = |pass_type: &str, capture_type: &str| {
+            format!(
+                r#"
+                {{
+                    "nodes": [
+                        {{"class": "ticker", "name":"ticker1", "period": 50, "limit":1}},
+                        {{"class": "test_passthrough", "name":"pass1"}},
+                        {{"class": "capture", "name":"capture1"}}
+                    ],
+                    "connections": [
+                        {{"source": {{"name":"ticker1"}}, "dest": {{"name": "pass1"}}, "type": {pass_type}, "allow_lossy": true}},
+                        {{"source": {{"name":"pass1"}}, "dest": {{"name": "capture1"}}, "type": {capture_type}}}
+                    ],
+                    "strict": true
+                }}"#
+            )
+        };
+        let lossy = [
+            (r#"{"optional": "float"}"#, r#"{"optional": "integer"}"#),
+            (
+                r#"{"list": {"optional": "float"}}"#,
+                r#"{"list": {"optional": "integer"}}"#,
+            ),
+            (r#""float""#, r#"{"oneof": ["integer", "boolean"]}"#),
+            (r#"{"oneof": ["float", "boolean"]}"#, r#""integer""#),
+            // Keys not in the schema are dropped when decoding
+            (r#""text/json""#, r#"{"dict": {"a": "integer"}}"#),
+        ];
+        for (pass_type, capture_type) in lossy {
+            assert!(
+                matches!(
+                    FlowState::new(&flow_text(pass_type, capture_type)),
+                    Err(Error::ConversionError(_))
+                ),
+                "{pass_type} -> {capture_type}"
+            );
+        }
+        // A member holds the value without losing information
+        assert!(FlowState::new(&flow_text(
+            r#""float""#,
+            r#"{"oneof": ["integer", "text/plain"]}"#
+        ))
+        .is_ok());
+        assert!(FlowState::new(&flow_text(
+            r#"{"optional": "integer"}"#,
+            r#"{"optional": "integer"}"#
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_max_nesting_depth() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 50, "limit":1},
+                    {"class": "test_passthrough", "name":"pass1"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "pass1"}, "type": "any"},
+                    {"source": {"name":"pass1"}, "dest": {"name": "capture1"}, "type": {"list": {"list": "integer"}}}
+                ]
+            }"#;
+        assert!(FlowState::new(json_str).is_ok());
+        let err = FlowState::builder()
+            .max_nesting_depth(1)
+            .build(json_str)
+            .unwrap_err();
+        assert!(err.to_string().contains("maximum depth of 1"), "{err}");
+    }
+
+    #[test]
+    fn test_type_inference() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 50, "limit":1},
+                    {"class": "test_passthrough", "name":"pass1"},
+                    {"class": "test_passthrough", "name":"pass2"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "pass1"}},
+                    {"source": {"name":"pass1"}, "dest": {"name": "pass2"}},
+                    {"source": {"name":"pass2"}, "dest": {"name": "capture1"}, "type": "text/plain"}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+        run_until_idle(&mut flow);
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![&MessageData::from_str("0")]
+        );
+    }
+
+    #[test]
+    fn test_type_inference_errors() {
+        let conflicting = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 50, "limit":1},
+                    {"class": "append", "name":"append1", "what_to_append":" test"},
+                    {"class": "test_passthrough", "name":"pass1"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "pass1"}},
+                    {"source": {"name":"append1"}, "dest": {"name": "pass1"}},
+                    {"source": {"name":"pass1"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        match FlowState::new(conflicting) {
+            Err(Error::TypeError(reason)) => {
+                assert!(reason.contains("pass1[0]"), "{reason}");
+                assert!(reason.contains("conflicting"), "{reason}");
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+
+        let unresolved = r#"
+            {
+                "nodes": [
+                    {"class": "test_passthrough", "name":"pass1"},
+                    {"class": "test_passthrough", "name":"pass2"},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"pass1"}, "dest": {"name": "pass2"}},
+                    {"source": {"name":"pass2"}, "dest": {"name": "pass1"}},
+                    {"source": {"name":"pass2"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        assert!(matches!(
+            FlowState::new(unresolved),
+            Err(Error::TypeError(_))
+        ));
+    }
+
+    /// Appends " async" to text messages after a delay, on the Tokio runtime.
+    #[cfg(feature = "async")]
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestAsyncAppendNode {
+        #[serde(flatten)]
+        common: NodeCommon,
+        #[serde(skip)]
+        runner: Option<crate::AsyncNodeRunner>,
+    }
+
+    #[cfg(feature = "async")]
+    struct AsyncAppend;
+
+    #[cfg(feature = "async")]
+    impl crate::AsyncNode for AsyncAppend {
+        async fn run(&mut self, msg: Message, _input: usize) -> NodeFunctionResult {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let text = msg.data.as_text().unwrap().clone() + " async";
+            Ok(vec![NodeOutput::new(
+                0,
+                Message::new(MessageData::from_string(&text)),
+            )])
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[typetag::serde(name = "test_async_append")]
+    impl Node for TestAsyncAppendNode {
+        fn common(&self) -> &NodeCommon {
+            &self.common
+        }
+
+        fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>, _clock: NodeClock) {
+            self.runner = Some(crate::AsyncNodeRunner::spawn(
+                AsyncAppend,
+                &self.common.name,
+                self.num_outputs(),
+                event_sender.unwrap(),
+            ));
+        }
+
+        fn run(&mut self, msg: &Message, index: usize) -> NodeFunctionResult {
+            self.runner.as_ref().unwrap().run(msg, index)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn num_inputs(&self) -> usize {
+            1
+        }
+
+        fn num_outputs(&self) -> usize {
+            1
+        }
+
+        fn input_type(&self, _index: usize) -> Option<&MessageType> {
+            static INPUT_TYPE: MessageType = MessageType::Text(crate::TextContentType::Plain);
+            Some(&INPUT_TYPE)
+        }
+
+        fn output_type(&self, _index: usize) -> &MessageType {
+            static OUTPUT_TYPE: MessageType = MessageType::Text(crate::TextContentType::Plain);
+            &OUTPUT_TYPE
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(start_paused = true)]
+    async fn test_run_async_ticker() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "ticker", "name":"ticker1", "period": 500, "limit": 3},
+                    {"class": "capture", "name":"capture1"}
+                ],
+                "connections": [
+                    {"source": {"name":"ticker1"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        let mut flow = FlowState::builder()
+            .clock(crate::TokioClock::new())
+            .build(json_str)
+            .unwrap();
+        // The paused clock advances whenever the runtime is idle, so this doesn't take 2 s
+        let res = tokio::time::timeout(Duration::from_secs(2), flow.run_async()).await;
+        assert!(res.is_err());
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![
+                &MessageData::Int(0),
+                &MessageData::Int(1),
+                &MessageData::Int(2)
+            ]
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(start_paused = true)]
+    async fn test_run_async_node() {
+        let json_str = r#"
+            {
+                "nodes": [
+                    {"class": "test_async_append", "name":"async1"},
+                    {"class": "capture", "name":"capture1"},
+                    {"class": "terminate", "name":"terminate1"}
+                ],
+                "connections": [
+                    {"source": {"name":"async1"}, "dest": {"name": "capture1"}}
+                ]
+            }"#;
+        let mut flow = FlowState::new(json_str).unwrap();
+        let inputs = [("async1", "a"), ("async1", "b"), ("terminate1", "")];
+        for (name, text) in inputs {
+            flow.dispatch(Event::MessageTo(MessageTo {
+                message: Message::new(MessageData::from_str(text)),
+                to: NodePort {
+                    name: name.to_string(),
+                    index: 0,
+                },
+            }));
+        }
+        let res = tokio::time::timeout(Duration::from_secs(1), flow.run_async()).await;
+        // The flow terminates before the outputs of the node are ready
+        res.unwrap().unwrap();
+        assert!(captured_data(&flow, "capture1").is_empty());
+
+        let res = tokio::time::timeout(Duration::from_secs(1), flow.run_async()).await;
+        assert!(res.is_err());
+        assert_eq!(
+            captured_data(&flow, "capture1"),
+            vec![
+                &MessageData::from_str("a async"),
+                &MessageData::from_str("b async")
+            ]
+        );
     }
 }
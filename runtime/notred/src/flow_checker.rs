@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::*;
+
 use crate::common::*;
 use crate::errors::Error;
 use crate::node::Node;
 use crate::node_util::node_by_name;
-use crate::{find_conversion, no_conversion};
+use crate::{find_explicit_conversion, no_conversion, ConversionRegistry, MessageType};
 
 pub fn check_flow(nodes: &Vec<Box<dyn Node>>, connections: &Vec<Connection>) -> Result<(), Error> {
     for c in connections {
@@ -32,35 +37,211 @@ pub fn check_flow(nodes: &Vec<Box<dyn Node>>, connections: &Vec<Connection>) ->
         }
     }
     // TODO: warn if inputs or outputs are not connected?
-    return Result::Ok(());
+    Result::Ok(())
+}
+
+/// Types of the node outputs, by node name and output index.
+pub type OutputTypes = HashMap<(String, usize), MessageType>;
+
+/// Type of the messages arriving at a node input.
+enum InputType {
+    Known(MessageType),
+    Unknown,
+    /// The connections to the input deliver messages of different types.
+    Conflict(String),
+}
+
+fn infer_input_type(
+    node: &dyn Node,
+    index: usize,
+    connections: &[Connection],
+    output_types: &OutputTypes,
+) -> InputType {
+    if let Some(input_type) = node.input_type(index) {
+        return InputType::Known(input_type.clone());
+    }
+    let mut res: Option<(&MessageType, &Connection)> = None;
+    for c in connections
+        .iter()
+        .filter(|c| c.dest.name == node.common().name && c.dest.index == index)
+    {
+        let source_type = match c
+            .dest_type
+            .as_ref()
+            .or_else(|| output_types.get(&(c.source.name.clone(), c.source.index)))
+        {
+            Some(source_type) => source_type,
+            None => return InputType::Unknown,
+        };
+        match res {
+            None => res = Some((source_type, c)),
+            Some((first_type, first)) if first_type != source_type => {
+                return InputType::Conflict(format!(
+                    "{first_type} from {}[{}], {source_type} from {}[{}]",
+                    first.source.name, first.source.index, c.source.name, c.source.index
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    match res {
+        Some((input_type, _)) => InputType::Known(input_type.clone()),
+        None => InputType::Unknown,
+    }
+}
+
+/// Infers the types of the node outputs.
+///
+/// Outputs of pass-through nodes get their types from the types of the inputs (see
+/// Node::infer_output_type), so the types are propagated along the connections until nothing
+/// changes. A connected output whose type can't be determined, e.g. because the node receives
+/// conflicting types or is part of a loop of pass-through nodes, is an error.
+pub fn infer_types(
+    nodes: &[Box<dyn Node>],
+    connections: &[Connection],
+) -> Result<OutputTypes, Error> {
+    let mut output_types = OutputTypes::new();
+    loop {
+        let mut changed = false;
+        for node in nodes {
+            let name = &node.common().name;
+            let input_types: Vec<Option<MessageType>> = (0..node.num_inputs())
+                .map(
+                    |i| match infer_input_type(node.as_ref(), i, connections, &output_types) {
+                        InputType::Known(input_type) => Some(input_type),
+                        _ => None,
+                    },
+                )
+                .collect();
+            for index in 0..node.num_outputs() {
+                let key = (name.clone(), index);
+                if output_types.contains_key(&key) {
+                    continue;
+                }
+                if let Some(output_type) = node.infer_output_type(index, &input_types) {
+                    output_types.insert(key, output_type);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for c in connections {
+        if output_types.contains_key(&(c.source.name.clone(), c.source.index)) {
+            continue;
+        }
+        let node = node_by_name(nodes, c.source.name.as_str()).unwrap();
+        for i in 0..node.num_inputs() {
+            if let InputType::Conflict(details) =
+                infer_input_type(node, i, connections, &output_types)
+            {
+                return Err(Error::TypeError(format!(
+                    "type of output {}[{}] can't be inferred: input {}[{i}] receives conflicting types ({details})",
+                    c.source.name, c.source.index, c.source.name
+                )));
+            }
+        }
+        return Err(Error::TypeError(format!(
+            "type of output {}[{}] can't be inferred from the types of the inputs",
+            c.source.name, c.source.index
+        )));
+    }
+    Ok(output_types)
 }
 
 pub fn find_conversions(
     nodes: &Vec<Box<dyn Node>>,
     connections: &mut Vec<Connection>,
+    output_types: &OutputTypes,
+    registry: &ConversionRegistry,
+    strict: bool,
+    max_depth: usize,
 ) -> Result<(), Error> {
     for c in connections {
-        let source_node = node_by_name(nodes, c.source.name.as_str()).unwrap();
-        let source_index = c.source.index;
-        let source_message_type = source_node.output_type(source_index);
+        let source_message_type = &output_types[&(c.source.name.clone(), c.source.index)];
 
         let dest_node = node_by_name(nodes, c.dest.name.as_str()).unwrap();
         let dest_index = c.dest.index;
-        if dest_node.input_type(dest_index).is_none() {
-            c.conversion = Some(no_conversion);
-            c.dest_type = Some(source_message_type.clone());
-            continue;
-        }
-        let dest_message_type = dest_node.input_type(dest_index).unwrap();
+        let dest_message_type = match (dest_node.input_type(dest_index), &c.dest_type) {
+            (None, None) if c.convert.is_some() => {
+                return Err(Error::ConversionError(format!(
+                    "connection {}[{}] -> {}[{}]: the destination type must be declared to use an explicit conversion",
+                    c.source.name, c.source.index, c.dest.name, c.dest.index
+                )));
+            }
+            (None, None) => {
+                c.conversion = Some(Arc::new(no_conversion));
+                c.dest_type = Some(source_message_type.clone());
+                continue;
+            }
+            (None, Some(declared_type)) => declared_type,
+            (Some(input_type), None) => input_type,
+            (Some(input_type), Some(declared_type)) => {
+                if input_type != declared_type {
+                    return Err(Error::ConversionError(format!(
+                        "connection {}[{}] -> {}[{}]: declared type {declared_type} doesn't match the input type {input_type}",
+                        c.source.name, c.source.index, c.dest.name, c.dest.index
+                    )));
+                }
+                input_type
+            }
+        };
 
-        let res = find_conversion(&source_message_type, &dest_message_type);
+        let res = match &c.convert {
+            Some(explicit) => find_explicit_conversion(
+                &explicit.name,
+                source_message_type,
+                dest_message_type,
+                max_depth,
+            ),
+            None => match registry.find_path(source_message_type, dest_message_type, max_depth) {
+                Ok(path) => {
+                    if path.steps.len() > 1 {
+                        let steps: Vec<String> = path.steps.iter().map(|t| t.to_string()).collect();
+                        info!(
+                            "connection {}[{}] -> {}[{}]: converting {source_message_type} -> {}",
+                            c.source.name,
+                            c.source.index,
+                            c.dest.name,
+                            c.dest.index,
+                            steps.join(" -> ")
+                        );
+                    }
+                    match &path.loss {
+                        Some(loss) if strict && !c.allow_lossy => {
+                            return Err(Error::ConversionError(format!(
+                                "connection {}[{}] -> {}[{}]: conversion from {source_message_type} to {dest_message_type} is lossy ({loss}); set \"allow_lossy\" or use an explicit conversion",
+                                c.source.name, c.source.index, c.dest.name, c.dest.index
+                            )));
+                        }
+                        Some(loss) if !c.allow_lossy => {
+                            warn!(
+                                "connection {}[{}] -> {}[{}]: conversion from {source_message_type} to {dest_message_type} is lossy: {loss}",
+                                c.source.name, c.source.index, c.dest.name, c.dest.index
+                            );
+                        }
+                        _ => {}
+                    }
+                    Ok(path.converter)
+                }
+                Err(e) => Err(e),
+            },
+        };
         match res {
             Ok(conv) => {
                 c.conversion = Some(conv);
                 c.dest_type = Some(dest_message_type.clone())
             }
-            Err(e) => return Err(Error::ConversionError(e.to_string())),
+            Err(e) => {
+                return Err(Error::ConversionError(format!(
+                    "connection {}[{}] -> {}[{}]: {e}",
+                    c.source.name, c.source.index, c.dest.name, c.dest.index
+                )))
+            }
         }
     }
-    return Ok(());
+    Ok(())
 }
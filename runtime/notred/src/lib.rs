@@ -1,11 +1,25 @@
+#[cfg(feature = "async")]
+pub use async_node::*;
+pub use clock::*;
 pub use common::*;
+pub use conversion_registry::*;
 pub use errors::*;
+pub use event_queue::*;
+pub use executor::Executor;
 pub use flow::*;
 pub use message::*;
 
+#[cfg(feature = "async")]
+mod async_node;
+mod clock;
+mod codec;
 mod common;
 mod conversion;
+mod conversion_path;
+mod conversion_registry;
 mod errors;
+mod event_queue;
+mod executor;
 mod flow;
 mod flow_checker;
 mod loader;
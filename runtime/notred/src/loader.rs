@@ -1,8 +1,7 @@
-use serde;
 use serde::{Deserialize, Serialize};
-use serde_json;
 
 use crate::common::Connection;
+use crate::event_queue::QueueConfig;
 use crate::node::Node;
 use crate::Error;
 
@@ -10,6 +9,12 @@ use crate::Error;
 pub(crate) struct LoadedFlowDescription {
     pub(crate) nodes: Vec<Box<dyn Node>>,
     pub(crate) connections: Vec<Connection>,
+    /// Whether lossy implicit conversions are errors, see FlowStateBuilder::strict.
+    #[serde(default)]
+    pub(crate) strict: bool,
+    /// Settings of the event queue, see FlowStateBuilder::queue_capacity.
+    #[serde(default)]
+    pub(crate) queue: Option<QueueConfig>,
 }
 
 impl LoadedFlowDescription {
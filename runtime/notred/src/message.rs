@@ -1,7 +1,15 @@
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::conversion;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::Error as _;
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{conversion, conversion_path};
 
 /// MessageType encodes various types of messages.
 ///
@@ -12,7 +20,12 @@ use crate::conversion;
 /// The following overview of the message types applies to both MessageType and MessageData:
 ///
 /// - Int: 64-bit signed integer
-/// - Float: 32-bit floating point number
+/// - FixedInt: integer of a fixed width and signedness (e.g. uint8 or int32), for values like
+///   hardware registers; has an "int_type" property which sets the range of the value.
+/// - Float: 64-bit floating point number
+/// - Bool: boolean value (true or false)
+/// - Null: marker for "no value"; has no associated data
+/// - Timestamp: point in time, in UTC
 /// - Text: Unicode string (i.e. String); has an additional property "content_type" which is a hint
 ///   about the nature of the text.
 /// - Binary: vector of bytes; similar to Text, has a "content_type" property to hint what kind of
@@ -20,15 +33,55 @@ use crate::conversion;
 /// - Dict: in MessageType, a dictionary (map) from string keys to MessageType values. This map is
 ///   also known as "schema". In MessageData, the Dict is a map from string keys to MessageData
 ///   values. The type of data for the given key matches the type indicated in the schema.
+/// - List: in MessageType, a list of elements of the given MessageType. In MessageData, a vector of
+///   MessageData values, each of which matches the element type.
+///
+/// MessageType additionally has variants which describe a set of possible types. There are no
+/// matching MessageData variants; the data always holds a value of one of the concrete types.
+///
+/// - Any: a value of any type.
+/// - Optional: a value of the inner type, or Null. In a dictionary schema, an Optional key may also
+///   be missing from the data.
+/// - OneOf: a value of one of the listed types (a union). Converting to a union picks the first
+///   member which accepts the value, preferring the member of the same type as the value, then the
+///   members which can hold it without losing information.
+///
+/// # String representation
+///
+/// The Display implementation produces strings like `integer`, `text/csv`, `list<float>` or
+/// `dict<"a": integer, "b": optional<text/plain>>`, which can be parsed back with FromStr.
 ///
+/// # JSON representation
+///
+/// MessageType is represented in JSON as a string for the simple types, matching the Display
+/// implementation: `"integer"`, `"uint8"`, `"float"`, `"boolean"`, `"null"`, `"timestamp"`, `"text/plain"`,
+/// `"text/json"`, `"binary/unknown"`. Dictionaries and lists are objects with a single key:
+/// `{"dict": {"key1": "integer", "key2": {"list": "float"}}}`, `{"list": "text/plain"}`.
+/// Similarly, Any is `"any"`, while Optional and OneOf are `{"optional": "integer"}` and
+/// `{"oneof": ["integer", "text/plain"]}`.
+///
+/// MessageData is represented as an object holding the type and the value:
+/// `{"type": "integer", "value": 42}`. The value uses the natural JSON representation of the data;
+/// binary data is encoded as a base64 string and timestamps as RFC 3339 strings. For example:
+/// `{"type": {"dict": {"t": "timestamp"}}, "value": {"t": "2022-08-01T12:00:00Z"}}`.
+/// The element type of an empty list can't be determined from the data and is recorded as "null",
+/// and the element type of a list holding values of different types is a oneof of these types.
 ///
 #[derive(Debug, Clone, PartialEq)]
 pub enum MessageType {
     Text(TextContentType),
     Binary(BinaryContentType),
     Int,
+    FixedInt(IntType),
     Float,
+    Bool,
+    Null,
+    Timestamp,
     Dict(DictSchema),
+    List(Box<MessageType>),
+    Any,
+    Optional(Box<MessageType>),
+    OneOf(Vec<MessageType>),
 }
 
 impl Display for MessageType {
@@ -37,25 +90,286 @@ impl Display for MessageType {
             MessageType::Text(tct) => match tct {
                 TextContentType::Plain => "text/plain",
                 TextContentType::Json => "text/json",
+                TextContentType::Csv => "text/csv",
+                TextContentType::Yaml => "text/yaml",
+                TextContentType::Xml => "text/xml",
+                TextContentType::Base64 => "text/base64",
+                TextContentType::Hex => "text/hex",
+                TextContentType::Other(subtype) => return write!(f, "text/{subtype}"),
             },
             MessageType::Binary(bct) => match bct {
                 BinaryContentType::Unknown => "binary/unknown",
+                BinaryContentType::OctetStream => "binary/octet-stream",
+                BinaryContentType::Cbor => "binary/cbor",
+                BinaryContentType::MessagePack => "binary/msgpack",
             },
             MessageType::Int => "integer",
+            MessageType::FixedInt(int_type) => int_type.name(),
             MessageType::Float => "float",
-            MessageType::Dict(_) => "dictionary",
+            MessageType::Bool => "boolean",
+            MessageType::Null => "null",
+            MessageType::Timestamp => "timestamp",
+            MessageType::Dict(schema) => {
+                // Sort the keys to make the representation stable
+                let schema: BTreeMap<&String, &MessageType> = schema.iter().collect();
+                let keys: Vec<String> = schema
+                    .iter()
+                    .map(|(key, t)| format!("{}: {t}", Value::String(key.to_string())))
+                    .collect();
+                return write!(f, "dict<{}>", keys.join(", "));
+            }
+            MessageType::List(element_type) => return write!(f, "list<{element_type}>"),
+            MessageType::Any => "any",
+            MessageType::Optional(inner) => return write!(f, "optional<{inner}>"),
+            MessageType::OneOf(members) => {
+                let members: Vec<String> = members.iter().map(|t| t.to_string()).collect();
+                return write!(f, "oneof<{}>", members.join(", "));
+            }
         };
         f.write_str(res)
     }
 }
 
+impl MessageType {
+    /// Returns the simple (not nested) type with the given Display name.
+    fn from_name(name: &str) -> Option<MessageType> {
+        match name {
+            "text/plain" => Some(MessageType::Text(TextContentType::Plain)),
+            "text/json" => Some(MessageType::Text(TextContentType::Json)),
+            "text/csv" => Some(MessageType::Text(TextContentType::Csv)),
+            "text/yaml" => Some(MessageType::Text(TextContentType::Yaml)),
+            "text/xml" => Some(MessageType::Text(TextContentType::Xml)),
+            "text/base64" => Some(MessageType::Text(TextContentType::Base64)),
+            "text/hex" => Some(MessageType::Text(TextContentType::Hex)),
+            "binary/unknown" => Some(MessageType::Binary(BinaryContentType::Unknown)),
+            "binary/octet-stream" => Some(MessageType::Binary(BinaryContentType::OctetStream)),
+            "binary/cbor" => Some(MessageType::Binary(BinaryContentType::Cbor)),
+            "binary/msgpack" => Some(MessageType::Binary(BinaryContentType::MessagePack)),
+            "integer" => Some(MessageType::Int),
+            "int8" => Some(MessageType::FixedInt(IntType::I8)),
+            "int16" => Some(MessageType::FixedInt(IntType::I16)),
+            "int32" => Some(MessageType::FixedInt(IntType::I32)),
+            "uint8" => Some(MessageType::FixedInt(IntType::U8)),
+            "uint16" => Some(MessageType::FixedInt(IntType::U16)),
+            "uint32" => Some(MessageType::FixedInt(IntType::U32)),
+            "uint64" => Some(MessageType::FixedInt(IntType::U64)),
+            "float" => Some(MessageType::Float),
+            "boolean" => Some(MessageType::Bool),
+            "null" => Some(MessageType::Null),
+            "timestamp" => Some(MessageType::Timestamp),
+            "any" => Some(MessageType::Any),
+            _ => match name.strip_prefix("text/") {
+                Some(subtype) if !subtype.is_empty() => Some(MessageType::Text(
+                    TextContentType::Other(subtype.to_string()),
+                )),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Error returned when parsing a MessageType from a string fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseMessageTypeError(String);
+
+impl Display for ParseMessageTypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid message type: {}", self.0)
+    }
+}
+
+/// Parses the representation produced by Display, e.g. `list<dict<"a": integer>>`.
+impl FromStr for MessageType {
+    type Err = ParseMessageTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = TypeParser { s, pos: 0 };
+        let res = parser.parse_type(0)?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error("unexpected characters after the type"));
+        }
+        Ok(res)
+    }
+}
+
+struct TypeParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> TypeParser<'a> {
+    fn error(&self, msg: &str) -> ParseMessageTypeError {
+        ParseMessageTypeError(format!("{msg} at position {} in '{}'", self.pos, self.s))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseMessageTypeError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{c}'")))
+        }
+    }
+
+    fn parse_name(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || "/-+._".contains(c)))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// Parses a dictionary key, written as a JSON string.
+    fn parse_key(&mut self) -> Result<String, ParseMessageTypeError> {
+        self.skip_whitespace();
+        let mut chars = self.rest().char_indices();
+        if !matches!(chars.next(), Some((_, '"'))) {
+            return Err(self.error("expected a quoted key"));
+        }
+        let mut escaped = false;
+        for (i, c) in chars {
+            match c {
+                '"' if !escaped => {
+                    let quoted = &self.rest()[..=i];
+                    let key =
+                        serde_json::from_str(quoted).map_err(|e| self.error(&e.to_string()))?;
+                    self.pos += i + 1;
+                    return Ok(key);
+                }
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        Err(self.error("unterminated key"))
+    }
+
+    fn parse_type(&mut self, depth: usize) -> Result<MessageType, ParseMessageTypeError> {
+        let name = self.parse_name();
+        if !self.eat('<') {
+            return MessageType::from_name(name)
+                .ok_or_else(|| self.error(&format!("unknown type '{name}'")));
+        }
+        if depth >= DEFAULT_MAX_NESTING_DEPTH {
+            return Err(self.error("types are nested too deeply"));
+        }
+        let res = match name {
+            "list" => MessageType::List(Box::new(self.parse_type(depth + 1)?)),
+            "optional" => MessageType::Optional(Box::new(self.parse_type(depth + 1)?)),
+            "oneof" => {
+                let mut members = vec![self.parse_type(depth + 1)?];
+                while self.eat(',') {
+                    members.push(self.parse_type(depth + 1)?);
+                }
+                MessageType::OneOf(members)
+            }
+            "dict" => {
+                let mut schema = DictSchema::new();
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    loop {
+                        let key = self.parse_key()?;
+                        self.expect(':')?;
+                        schema.insert(key, self.parse_type(depth + 1)?);
+                        if !self.eat(',') {
+                            break;
+                        }
+                    }
+                }
+                MessageType::Dict(schema)
+            }
+            _ => return Err(self.error(&format!("unknown type '{name}'"))),
+        };
+        self.expect('>')?;
+        Ok(res)
+    }
+}
+
+impl Serialize for MessageType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MessageType::Dict(schema) => {
+                // Sort the keys to make the representation stable
+                let schema: BTreeMap<&String, &MessageType> = schema.iter().collect();
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("dict", &schema)?;
+                map.end()
+            }
+            MessageType::List(element_type) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("list", element_type)?;
+                map.end()
+            }
+            MessageType::Optional(inner) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("optional", inner)?;
+                map.end()
+            }
+            MessageType::OneOf(members) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("oneof", members)?;
+                map.end()
+            }
+            _ => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum MessageTypeRepr {
+    Name(String),
+    Dict { dict: DictSchema },
+    List { list: Box<MessageType> },
+    Optional { optional: Box<MessageType> },
+    OneOf { oneof: Vec<MessageType> },
+}
+
+impl<'de> Deserialize<'de> for MessageType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match MessageTypeRepr::deserialize(deserializer)? {
+            MessageTypeRepr::Name(name) => MessageType::from_name(&name)
+                .ok_or_else(|| D::Error::custom(format!("unknown message type '{name}'"))),
+            MessageTypeRepr::Dict { dict } => Ok(MessageType::Dict(dict)),
+            MessageTypeRepr::List { list } => Ok(MessageType::List(list)),
+            MessageTypeRepr::Optional { optional } => Ok(MessageType::Optional(optional)),
+            MessageTypeRepr::OneOf { oneof } => Ok(MessageType::OneOf(oneof)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum MessageData {
     Text(Text),
     Binary(Binary),
     Int(i64),
-    Float(f32),
+    FixedInt(FixedInt),
+    Float(f64),
+    Bool(bool),
+    Null,
+    Timestamp(DateTime<Utc>),
     Dict(Dict),
+    List(Vec<MessageData>),
 }
 
 impl MessageData {
@@ -66,6 +380,7 @@ impl MessageData {
             None
         }
     }
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(text: &str) -> MessageData {
         MessageData::Text(Text {
             value: text.to_string(),
@@ -73,24 +388,103 @@ impl MessageData {
         })
     }
 
-    pub fn from_string(text: &String) -> MessageData {
+    pub fn from_string(text: &str) -> MessageData {
         MessageData::Text(Text {
-            value: text.clone(),
+            value: text.to_string(),
             content_type: TextContentType::Plain,
         })
     }
 }
 
+impl Serialize for MessageData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = conversion::message_data_to_json(self).map_err(S::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", &conversion::value_type(self, &MessageType::Null))?;
+        map.serialize_entry("value", &value)?;
+        map.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MessageDataRepr {
+    #[serde(rename = "type")]
+    message_type: MessageType,
+    value: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for MessageData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MessageDataRepr::deserialize(deserializer)?;
+        conversion::json_to_message_data(&repr.value, &repr.message_type).map_err(D::Error::custom)
+    }
+}
+
+/// Number of bytes of binary data shown when displaying MessageData, unless set by the precision.
+pub const DEFAULT_DISPLAY_BINARY_LEN: usize = 16;
+
+/// Displays the data as compact JSON-like text, with dictionary keys sorted. Binary data is shown
+/// as its length and a hex dump of the first bytes, e.g. `<3 bytes: 00 01 ff>`. The precision
+/// sets the number of bytes shown (`format!("{md:.4}")`), [`DEFAULT_DISPLAY_BINARY_LEN`] by
+/// default.
 impl Display for MessageData {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let res = match &self {
-            MessageData::Text(t) => format!("\"{}\"", t.value),
-            MessageData::Binary(_) => format!("<binary data>"), // FIXME
-            MessageData::Int(i) => format!("{i}"),
-            MessageData::Float(f) => format!("{f}"),
-            MessageData::Dict(_) => format!("<dictionary>"), // FIXME
-        };
-        f.write_str(res.as_str())
+        let max_binary_len = f.precision().unwrap_or(DEFAULT_DISPLAY_BINARY_LEN);
+        write_message_data(f, self, max_binary_len)
+    }
+}
+
+fn write_message_data(
+    f: &mut Formatter<'_>,
+    md: &MessageData,
+    max_binary_len: usize,
+) -> std::fmt::Result {
+    match md {
+        MessageData::Text(t) => write!(f, "{}", Value::String(t.value.clone())),
+        MessageData::Binary(b) => {
+            let len = b.value.len();
+            let mut dump: Vec<String> = b.value[..len.min(max_binary_len)]
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            if len > max_binary_len {
+                dump.push("...".to_string());
+            }
+            match len {
+                0 => f.write_str("<0 bytes>"),
+                1 => write!(f, "<1 byte: {}>", dump.join(" ")),
+                _ => write!(f, "<{len} bytes: {}>", dump.join(" ")),
+            }
+        }
+        MessageData::Int(i) => write!(f, "{i}"),
+        MessageData::FixedInt(i) => write!(f, "{}", i.value),
+        MessageData::Float(v) => write!(f, "{v}"),
+        MessageData::Bool(b) => write!(f, "{b}"),
+        MessageData::Null => f.write_str("null"),
+        MessageData::Timestamp(t) => f.write_str(&t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        MessageData::Dict(d) => {
+            let data: BTreeMap<&String, &MessageData> = d.data.iter().collect();
+            f.write_str("{")?;
+            for (i, (key, value)) in data.into_iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}: ", Value::String(key.to_string()))?;
+                write_message_data(f, value, max_binary_len)?;
+            }
+            f.write_str("}")
+        }
+        MessageData::List(l) => {
+            f.write_str("[")?;
+            for (i, value) in l.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_message_data(f, value, max_binary_len)?;
+            }
+            f.write_str("]")
+        }
     }
 }
 
@@ -98,11 +492,88 @@ impl Display for MessageData {
 pub enum TextContentType {
     Plain,
     Json,
+    Csv,
+    Yaml,
+    Xml,
+    /// Binary data encoded as base64
+    Base64,
+    /// Binary data encoded as hexadecimal digits
+    Hex,
+    /// Any other text type, holding the MIME subtype (e.g. "html" for text/html)
+    Other(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinaryContentType {
     Unknown,
+    /// Raw bytes without any structure
+    OctetStream,
+    Cbor,
+    MessagePack,
+}
+
+/// Width and signedness of a FixedInt. 64-bit signed integers are the Int type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntType {
+    fn name(&self) -> &'static str {
+        match self {
+            IntType::I8 => "int8",
+            IntType::I16 => "int16",
+            IntType::I32 => "int32",
+            IntType::U8 => "uint8",
+            IntType::U16 => "uint16",
+            IntType::U32 => "uint32",
+            IntType::U64 => "uint64",
+        }
+    }
+
+    pub fn min(&self) -> i128 {
+        match self {
+            IntType::I8 => i8::MIN.into(),
+            IntType::I16 => i16::MIN.into(),
+            IntType::I32 => i32::MIN.into(),
+            IntType::U8 | IntType::U16 | IntType::U32 | IntType::U64 => 0,
+        }
+    }
+
+    pub fn max(&self) -> i128 {
+        match self {
+            IntType::I8 => i8::MAX.into(),
+            IntType::I16 => i16::MAX.into(),
+            IntType::I32 => i32::MAX.into(),
+            IntType::U8 => u8::MAX.into(),
+            IntType::U16 => u16::MAX.into(),
+            IntType::U32 => u32::MAX.into(),
+            IntType::U64 => u64::MAX.into(),
+        }
+    }
+}
+
+/// Integer of a fixed width. The value is always within the range of the int_type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedInt {
+    pub value: i128,
+    pub int_type: IntType,
+}
+
+impl FixedInt {
+    /// Returns the FixedInt with the given value, or None if the value is out of range.
+    pub fn new(value: i128, int_type: IntType) -> Option<FixedInt> {
+        if value < int_type.min() || value > int_type.max() {
+            return None;
+        }
+        Some(FixedInt { value, int_type })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -130,6 +601,13 @@ pub enum FindConversionError {
     NoImplicitConversion,
     ConversionNotImplemented,
     NoImplicitConversionDetailed(String),
+    InvalidExplicitConversion(String),
+    /// Dictionary or list types are nested deeper than the limit given to the lookup, see
+    /// [`FlowStateBuilder::max_nesting_depth`](crate::FlowStateBuilder::max_nesting_depth).
+    NestingTooDeep {
+        key_path: String,
+        max_depth: usize,
+    },
 }
 
 impl Display for FindConversionError {
@@ -142,33 +620,380 @@ impl Display for FindConversionError {
             FindConversionError::NoImplicitConversionDetailed(details) => {
                 format!("no implicit conversion: {details}")
             }
+            FindConversionError::InvalidExplicitConversion(details) => {
+                format!("invalid explicit conversion: {details}")
+            }
+            FindConversionError::NestingTooDeep {
+                key_path,
+                max_depth,
+            } => {
+                format!("type nesting exceeds the maximum depth of {max_depth} at key '{key_path}'")
+            }
         };
         f.write_str(res.as_str())
     }
 }
 
+/// Error returned by a MessageConverter when a particular value can't be converted.
+///
+/// For nested dictionaries and lists, `src` and `dst_type` refer to the innermost value which failed
+/// to convert, and `key_path` holds the dot-separated path to it from the top-level value. List
+/// elements appear in the path as their index.
 #[derive(Debug, Clone)]
 pub struct ConversionError {
-    // TODO:
-    // src: MessageData,
-    // dst_type: MessageType,
-    // err_msg: String,
+    pub src: Box<MessageData>,
+    pub dst_type: Box<MessageType>,
+    pub err_msg: String,
+    pub key_path: Option<String>,
+}
+
+impl ConversionError {
+    pub fn new(src: &MessageData, dst_type: &MessageType, err_msg: &str) -> ConversionError {
+        ConversionError {
+            src: Box::new(src.clone()),
+            dst_type: Box::new(dst_type.clone()),
+            err_msg: err_msg.to_string(),
+            key_path: None,
+        }
+    }
+
+    /// Prepends `key` to the key path, used when the error propagates out of a nested dictionary.
+    pub fn in_key(mut self, key: &str) -> ConversionError {
+        self.key_path = Some(match self.key_path {
+            None => key.to_string(),
+            Some(path) => format!("{key}.{path}"),
+        });
+        self
+    }
 }
 
 impl Display for ConversionError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Failed to convert")
+        write!(f, "Failed to convert {} to {}", self.src, self.dst_type)?;
+        if let Some(key_path) = &self.key_path {
+            write!(f, " (key '{key_path}')")?;
+        }
+        write!(f, ": {}", self.err_msg)
     }
 }
 
 pub type ConversionResult = Result<MessageData, ConversionError>;
-pub type MessageConverter = fn(src: &MessageData, dst: &MessageType) -> ConversionResult;
+pub type MessageConverter =
+    Arc<dyn Fn(&MessageData, &MessageType) -> ConversionResult + Send + Sync>;
 pub type FindConversionResult = Result<MessageConverter, FindConversionError>;
 
-pub fn find_conversion(src: &MessageType, dst: &MessageType) -> FindConversionResult {
-    conversion::find(src, dst)
+/// Finds the implicit conversion from `src` to `dst`.
+///
+/// Dictionary and list types nested deeper than `max_depth` are rejected with
+/// [`FindConversionError::NestingTooDeep`], so that types loaded from untrusted flow files can't
+/// exhaust the stack. Binary data (e.g. CBOR) nested deeper can't be decoded either.
+pub fn find_conversion(
+    src: &MessageType,
+    dst: &MessageType,
+    max_depth: usize,
+) -> FindConversionResult {
+    conversion::find(src, dst, max_depth)
+}
+
+/// Default limit of the nesting depth of types, see [`find_conversion`]. It also applies to the
+/// types parsed with FromStr.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 64;
+
+/// Chain of implicit conversions between two types, composed into a single converter.
+#[derive(Clone)]
+pub struct ConversionPath {
+    /// Types the message is converted to at each step; the last one is the destination type.
+    pub steps: Vec<MessageType>,
+    pub converter: MessageConverter,
+    /// Description of the information lost in the conversion, None if it is lossless.
+    pub loss: Option<String>,
+}
+
+impl Debug for ConversionPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConversionPath")
+            .field("steps", &self.steps)
+            .field("loss", &self.loss)
+            .finish()
+    }
+}
+
+/// Finds the cheapest chain of implicit conversions from `src` to `dst`. See
+/// [`find_conversion`] for `max_depth`.
+pub fn find_conversion_path(
+    src: &MessageType,
+    dst: &MessageType,
+    max_depth: usize,
+) -> Result<ConversionPath, FindConversionError> {
+    conversion_path::find_path(src, dst, max_depth)
+}
+
+/// Finds the explicit conversion with the given name (e.g. "base64") between the given types. See
+/// [`find_conversion`] for `max_depth`.
+pub fn find_explicit_conversion(
+    name: &str,
+    src: &MessageType,
+    dst: &MessageType,
+    max_depth: usize,
+) -> FindConversionResult {
+    conversion::find_explicit(name, src, dst, max_depth)
 }
 
 pub fn no_conversion(src: &MessageData, dst: &MessageType) -> ConversionResult {
-    return conversion::identity(src, dst);
+    conversion::identity(src, dst)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn assert_type_json(mt: MessageType, expected: serde_json::Value) {
+        assert_eq!(serde_json::to_value(&mt).unwrap(), expected);
+        assert_eq!(serde_json::from_value::<MessageType>(expected).unwrap(), mt);
+    }
+
+    fn assert_data_json(md: MessageData, expected: serde_json::Value) {
+        assert_eq!(serde_json::to_value(&md).unwrap(), expected);
+        assert_eq!(serde_json::from_value::<MessageData>(expected).unwrap(), md);
+    }
+
+    #[test]
+    fn test_message_data_display() {
+        let binary = |len: u8| {
+            MessageData::Binary(Binary {
+                value: (0..len).collect(),
+                content_type: BinaryContentType::Unknown,
+            })
+        };
+        assert_eq!(binary(0).to_string(), "<0 bytes>");
+        assert_eq!(binary(1).to_string(), "<1 byte: 00>");
+        assert_eq!(
+            binary(17).to_string(),
+            "<17 bytes: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f ...>"
+        );
+        assert_eq!(format!("{:.2}", binary(3)), "<3 bytes: 00 01 ...>");
+        assert_eq!(format!("{:.0}", binary(3)), "<3 bytes: ...>");
+
+        assert_eq!(
+            MessageData::from_str("say \"hi\"\n").to_string(),
+            r#""say \"hi\"\n""#
+        );
+        let dict = MessageData::Dict(Dict {
+            data: HashMap::from([
+                ("b".to_string(), binary(2)),
+                (
+                    "a".to_string(),
+                    MessageData::List(vec![MessageData::Int(1), MessageData::Null]),
+                ),
+            ]),
+            schema: DictSchema::from([
+                (
+                    "b".to_string(),
+                    MessageType::Binary(BinaryContentType::Unknown),
+                ),
+                (
+                    "a".to_string(),
+                    MessageType::List(Box::new(MessageType::Int)),
+                ),
+            ]),
+        });
+        assert_eq!(
+            dict.to_string(),
+            r#"{"a": [1, null], "b": <2 bytes: 00 01>}"#
+        );
+        assert_eq!(
+            format!("{dict:.1}"),
+            r#"{"a": [1, null], "b": <2 bytes: 00 ...>}"#
+        );
+    }
+
+    #[test]
+    fn test_message_type_json() {
+        assert_type_json(MessageType::Int, json!("integer"));
+        assert_type_json(MessageType::Text(TextContentType::Json), json!("text/json"));
+        assert_type_json(
+            MessageType::Binary(BinaryContentType::Unknown),
+            json!("binary/unknown"),
+        );
+        assert_type_json(
+            MessageType::List(Box::new(MessageType::Float)),
+            json!({"list": "float"}),
+        );
+        assert_type_json(
+            MessageType::Dict(DictSchema::from([
+                ("b".to_string(), MessageType::Timestamp),
+                (
+                    "a".to_string(),
+                    MessageType::Dict(DictSchema::from([("c".to_string(), MessageType::Bool)])),
+                ),
+            ])),
+            json!({"dict": {"a": {"dict": {"c": "boolean"}}, "b": "timestamp"}}),
+        );
+        assert_eq!(
+            serde_json::to_string(&MessageType::Dict(DictSchema::from([
+                ("b".to_string(), MessageType::Null),
+                ("a".to_string(), MessageType::Null),
+            ])))
+            .unwrap(),
+            r#"{"dict":{"a":"null","b":"null"}}"#
+        );
+
+        assert_type_json(MessageType::Any, json!("any"));
+        assert_type_json(
+            MessageType::Optional(Box::new(MessageType::Int)),
+            json!({"optional": "integer"}),
+        );
+        assert_type_json(
+            MessageType::OneOf(vec![
+                MessageType::Int,
+                MessageType::List(Box::new(MessageType::Any)),
+            ]),
+            json!({"oneof": ["integer", {"list": "any"}]}),
+        );
+
+        assert_type_json(
+            MessageType::Text(TextContentType::Other("html".to_string())),
+            json!("text/html"),
+        );
+
+        assert!(serde_json::from_value::<MessageType>(json!("int")).is_err());
+        assert!(serde_json::from_value::<MessageType>(json!({"dict": "integer"})).is_err());
+        assert!(serde_json::from_value::<MessageType>(json!({"list": "integer", "x": 1})).is_err());
+    }
+
+    fn assert_type_str(mt: MessageType, expected: &str) {
+        assert_eq!(mt.to_string(), expected);
+        assert_eq!(expected.parse::<MessageType>().unwrap(), mt);
+    }
+
+    #[test]
+    fn test_message_type_str() {
+        assert_type_str(MessageType::Int, "integer");
+        assert_type_str(MessageType::Text(TextContentType::Csv), "text/csv");
+        assert_type_str(MessageType::Text(TextContentType::Yaml), "text/yaml");
+        assert_type_str(MessageType::Text(TextContentType::Xml), "text/xml");
+        assert_type_str(MessageType::FixedInt(IntType::U8), "uint8");
+        assert_type_str(MessageType::FixedInt(IntType::I32), "int32");
+        assert_type_str(MessageType::Text(TextContentType::Base64), "text/base64");
+        assert_type_str(MessageType::Text(TextContentType::Hex), "text/hex");
+        assert_type_str(
+            MessageType::Binary(BinaryContentType::OctetStream),
+            "binary/octet-stream",
+        );
+        assert_type_str(MessageType::Binary(BinaryContentType::Cbor), "binary/cbor");
+        assert_type_str(
+            MessageType::Binary(BinaryContentType::MessagePack),
+            "binary/msgpack",
+        );
+        assert_type_str(
+            MessageType::Text(TextContentType::Other("html".to_string())),
+            "text/html",
+        );
+        assert_type_str(
+            MessageType::List(Box::new(MessageType::Optional(Box::new(
+                MessageType::Float,
+            )))),
+            "list<optional<float>>",
+        );
+        assert_type_str(
+            MessageType::OneOf(vec![MessageType::Int, MessageType::Null]),
+            "oneof<integer, null>",
+        );
+        assert_type_str(MessageType::Dict(DictSchema::new()), "dict<>");
+        assert_type_str(
+            MessageType::Dict(DictSchema::from([
+                (
+                    "b".to_string(),
+                    MessageType::List(Box::new(MessageType::Any)),
+                ),
+                ("a, \"x\"".to_string(), MessageType::Bool),
+            ])),
+            r#"dict<"a, \"x\"": boolean, "b": list<any>>"#,
+        );
+        assert_eq!(
+            " list < dict<\"a\":integer> > ".parse::<MessageType>(),
+            Ok(MessageType::List(Box::new(MessageType::Dict(
+                DictSchema::from([("a".to_string(), MessageType::Int)])
+            ))))
+        );
+
+        for invalid in [
+            "",
+            "int",
+            "text/",
+            "list<integer",
+            "list<integer>>",
+            "list<>",
+            "vector<integer>",
+            "dict<a: integer>",
+            "dict<\"a\" integer>",
+            "oneof<integer,>",
+        ] {
+            assert!(invalid.parse::<MessageType>().is_err(), "{invalid}");
+        }
+        let deep = format!("{}integer{}", "list<".repeat(1000), ">".repeat(1000));
+        assert!(deep.parse::<MessageType>().is_err());
+    }
+
+    #[test]
+    fn test_message_data_json() {
+        assert_data_json(
+            MessageData::Int(42),
+            json!({"type": "integer", "value": 42}),
+        );
+        assert_data_json(
+            MessageData::from_str("hello"),
+            json!({"type": "text/plain", "value": "hello"}),
+        );
+        assert_data_json(
+            MessageData::Binary(Binary {
+                value: vec![1, 2, 3],
+                content_type: BinaryContentType::Unknown,
+            }),
+            json!({"type": "binary/unknown", "value": "AQID"}),
+        );
+        assert_data_json(
+            MessageData::List(vec![MessageData::Bool(true), MessageData::Bool(false)]),
+            json!({"type": {"list": "boolean"}, "value": [true, false]}),
+        );
+        assert_data_json(
+            MessageData::List(vec![]),
+            json!({"type": {"list": "null"}, "value": []}),
+        );
+        // Elements of different types
+        assert_data_json(
+            MessageData::List(vec![MessageData::Int(1), MessageData::from_str("x")]),
+            json!({"type": {"list": {"oneof": ["integer", "text/plain"]}}, "value": [1, "x"]}),
+        );
+        assert_data_json(
+            MessageData::List(vec![
+                MessageData::Int(1),
+                MessageData::Float(1.5),
+                MessageData::Int(2),
+            ]),
+            json!({"type": {"list": {"oneof": ["integer", "float"]}}, "value": [1, 1.5, 2]}),
+        );
+        assert_data_json(
+            MessageData::Dict(Dict {
+                data: HashMap::from([
+                    ("f".to_string(), MessageData::Float(0.5)),
+                    ("n".to_string(), MessageData::Null),
+                ]),
+                schema: DictSchema::from([
+                    ("f".to_string(), MessageType::Float),
+                    ("n".to_string(), MessageType::Null),
+                ]),
+            }),
+            json!({"type": {"dict": {"f": "float", "n": "null"}}, "value": {"f": 0.5, "n": null}}),
+        );
+
+        assert!(serde_json::from_value::<MessageData>(json!({"type": "integer"})).is_err());
+        assert!(
+            serde_json::from_value::<MessageData>(json!({"type": "integer", "value": "42"}))
+                .is_err()
+        );
+        assert!(serde_json::to_value(MessageData::Float(f64::NAN)).is_err());
+    }
 }
@@ -4,18 +4,38 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{NodeClock, TimerId};
 use crate::common::{EventSender, Message};
 use crate::{Error, MessageType};
 
-pub type NodeFunctionResult = Result<Option<Message>, Error>;
+/// Message emitted by a node on one of its outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeOutput {
+    pub index: usize,
+    pub message: Message,
+}
+
+impl NodeOutput {
+    pub fn new(index: usize, message: Message) -> NodeOutput {
+        NodeOutput { index, message }
+    }
+}
+
+/// Result of Node::run: zero or more messages, each on any of the node's outputs. Messages
+/// are dispatched in order, so several messages on the same output keep their relative order.
+pub type NodeFunctionResult = Result<Vec<NodeOutput>, Error>;
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct NodeCommon {
     pub name: String,
     #[serde(default)]
     pub log_inputs: bool,
     #[serde(default)]
     pub log_outputs: bool,
+    /// Number of bytes of binary data shown when logging messages, DEFAULT_DISPLAY_BINARY_LEN if
+    /// not set.
+    #[serde(default)]
+    pub log_binary_len: Option<usize>,
 }
 
 impl NodeCommon {
@@ -24,18 +44,51 @@ impl NodeCommon {
             name: name.to_string(),
             log_inputs: false,
             log_outputs: false,
+            log_binary_len: None,
         }
     }
 }
 
 #[typetag::serde(tag = "class")]
-pub trait Node: Debug + Any {
+pub trait Node: Debug + Any + Send {
     fn common(&self) -> &NodeCommon;
-    fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>);
+    /// Called once when the flow is built. `clock` is the clock of the flow; nodes reading the
+    /// time or starting timers keep it.
+    fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>, clock: NodeClock);
     fn run(&mut self, msg: &Message, _input: usize) -> NodeFunctionResult;
     fn as_any(&self) -> &dyn Any;
     fn num_inputs(&self) -> usize;
     fn num_outputs(&self) -> usize;
     fn input_type(&self, index: usize) -> Option<&MessageType>;
     fn output_type(&self, index: usize) -> &MessageType;
+
+    /// Determines the type of the given output from the types of the messages arriving at the
+    /// inputs. `input_types` has an element for each input, None if the type isn't known (yet).
+    ///
+    /// Nodes with fixed output types don't need to implement this. Pass-through nodes can return
+    /// the type of one of their inputs, or None until it is known; the flow checker calls this
+    /// again as the types of more inputs get inferred.
+    fn infer_output_type(
+        &self,
+        index: usize,
+        _input_types: &[Option<MessageType>],
+    ) -> Option<MessageType> {
+        Some(self.output_type(index).clone())
+    }
+
+    /// Returns another instance of the node, which the multi-threaded executor runs instead of this
+    /// one so that the node processes several messages at once. The instance is only used for one
+    /// call to run(), and is dropped afterwards.
+    ///
+    /// Nodes which keep state between messages must return None (the default), so that they
+    /// process one message at a time.
+    fn reentrant_instance(&self) -> Option<Box<dyn Node>> {
+        None
+    }
+
+    /// Called on the thread running the flow when a timer started with NodeClock::start_timer
+    /// elapses. Returns the messages to send, like run().
+    fn on_timer(&mut self, _timer: TimerId) -> NodeFunctionResult {
+        Ok(vec![])
+    }
 }
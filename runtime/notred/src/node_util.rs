@@ -1,22 +1,8 @@
 use crate::node::Node;
 
-pub fn node_by_name<'a>(nodes: &'a Vec<Box<dyn Node>>, name: &str) -> Option<&'a Box<dyn Node>> {
-    for n in nodes {
-        if n.common().name == name {
-            return Option::Some(n);
-        }
-    }
-    return Option::None;
-}
-
-pub fn node_by_name_mut<'a>(
-    nodes: &'a mut Vec<Box<dyn Node>>,
-    name: &str,
-) -> Option<&'a mut Box<dyn Node>> {
-    for n in nodes {
-        if n.common().name == name {
-            return Option::Some(n);
-        }
-    }
-    return Option::None;
+pub fn node_by_name<'a>(nodes: &'a [Box<dyn Node>], name: &str) -> Option<&'a dyn Node> {
+    nodes
+        .iter()
+        .find(|n| n.common().name == name)
+        .map(|n| n.as_ref())
 }
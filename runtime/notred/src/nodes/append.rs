@@ -3,13 +3,14 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use crate::clock::NodeClock;
 use crate::common::*;
 use crate::node::NodeFunctionResult;
 use crate::node::*;
 use crate::MessageType;
 use crate::TextContentType::Plain;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct AppendNode {
     #[serde(flatten)]
     common: NodeCommon,
@@ -23,12 +24,15 @@ impl Node for AppendNode {
     fn common(&self) -> &NodeCommon {
         &self.common
     }
-    fn create(&mut self, _event_sender: Option<Arc<Mutex<dyn EventSender>>>) {}
+    fn create(&mut self, _event_sender: Option<Arc<Mutex<dyn EventSender>>>, _clock: NodeClock) {}
     fn run(&mut self, msg: &Message, _index: usize) -> NodeFunctionResult {
-        if let MessageData::Text(text) = msg {
-            Ok(Some(MessageData::from_string(
-                &(text.value.clone() + &self.what_to_append),
-            )))
+        if let MessageData::Text(text) = &msg.data {
+            Ok(vec![NodeOutput::new(
+                0,
+                Message::new(MessageData::from_string(
+                    &(text.value.clone() + &self.what_to_append),
+                )),
+            )])
         } else {
             unimplemented!();
         }
@@ -55,6 +59,10 @@ impl Node for AppendNode {
         assert_eq!(index, 0);
         &APPEND_MESSAGE_TYPE
     }
+
+    fn reentrant_instance(&self) -> Option<Box<dyn Node>> {
+        Some(Box::new(self.clone()))
+    }
 }
 
 #[cfg(test)]
@@ -68,10 +76,15 @@ mod test {
         )
         .unwrap();
         assert_eq!(node.common().name, "node1");
+        let outputs = node
+            .run(&Message::new(MessageData::from_str("this is")), 0)
+            .expect("should not be an error");
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].index, 0);
         assert_eq!(
-            node.run(&MessageData::from_str("this is"), 0)
-                .expect("should not be an error")
-                .expect("should not be empty")
+            outputs[0]
+                .message
+                .data
                 .as_text()
                 .expect("should be a string"),
             "this is test"
@@ -3,6 +3,7 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use crate::clock::NodeClock;
 use crate::common::*;
 use crate::node::*;
 use crate::MessageType;
@@ -21,12 +22,12 @@ impl Node for CaptureNode {
         &self.common
     }
 
-    fn create(&mut self, _event_sender: Option<Arc<Mutex<dyn EventSender>>>) {}
+    fn create(&mut self, _event_sender: Option<Arc<Mutex<dyn EventSender>>>, _clock: NodeClock) {}
 
     fn run(&mut self, msg: &Message, index: usize) -> NodeFunctionResult {
         assert_eq!(index, 0);
         self.captured_messages.push(msg.clone());
-        Ok(None)
+        Ok(vec![])
     }
 
     fn as_any(&self) -> &dyn Any {
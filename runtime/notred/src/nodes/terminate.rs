@@ -3,6 +3,7 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use crate::clock::NodeClock;
 use crate::common::*;
 use crate::node::*;
 use crate::MessageType;
@@ -21,7 +22,7 @@ impl Node for TerminateNode {
         &self.common
     }
 
-    fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>) {
+    fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>, _clock: NodeClock) {
         self.event_sender = event_sender
     }
 
@@ -31,8 +32,8 @@ impl Node for TerminateNode {
             .unwrap()
             .lock()
             .unwrap()
-            .dispatch(Event::Terminate());
-        Ok(None)
+            .dispatch(Event::Terminate())?;
+        Ok(vec![])
     }
 
     fn as_any(&self) -> &dyn Any {
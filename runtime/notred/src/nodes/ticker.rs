@@ -1,9 +1,10 @@
 use std::any::Any;
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
 
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::clock::{NodeClock, TimerId};
 use crate::common::*;
 use crate::node::*;
 use crate::MessageType;
@@ -12,13 +13,17 @@ use crate::MessageType;
 struct TickerNode {
     #[serde(flatten)]
     common: NodeCommon,
+    #[serde(deserialize_with = "deserialize_period")]
     period: DurationMsec,
     limit: Option<usize>,
 
     #[serde(skip)]
-    thread_handle: Option<JoinHandle<()>>,
+    clock: Option<NodeClock>,
     #[serde(skip)]
-    terminate_tx: Option<std::sync::mpsc::SyncSender<()>>,
+    timer: Option<TimerId>,
+    /// Number of ticks sent so far
+    #[serde(skip)]
+    count: usize,
 }
 
 #[typetag::serde(name = "ticker")]
@@ -27,42 +32,29 @@ impl Node for TickerNode {
         &self.common
     }
 
-    fn create(&mut self, event_sender: Option<Arc<Mutex<dyn EventSender>>>) {
-        let period = self.period.to_duration();
-        let event_sender = event_sender.unwrap().clone();
-        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
-        let name = self.common.name.clone();
-        let mut limit = self.limit.clone();
-        let mut count: i64 = 0;
-        self.terminate_tx = Some(sender);
-        self.thread_handle = Some(std::thread::spawn(move || loop {
-            if receiver.recv_timeout(period).is_ok() {
-                return;
-            }
-            let mut r = event_sender.lock().unwrap();
-            r.dispatch(Event::MessageFrom(MessageFrom {
-                message: MessageData::Int(count),
-                from: NodePort {
-                    name: name.clone(),
-                    index: 0,
-                },
-            }));
-
-            if let Some(mut lim) = limit {
-                lim -= 1;
-                limit = Some(lim);
-                if lim == 0 {
-                    return;
-                }
-            }
-            count += 1;
-        }));
+    fn create(&mut self, _event_sender: Option<Arc<Mutex<dyn EventSender>>>, clock: NodeClock) {
+        if self.limit != Some(0) {
+            let period = self.period.to_duration();
+            self.timer = Some(clock.start_timer(period, Some(period)));
+        }
+        self.clock = Some(clock);
     }
 
     fn run(&mut self, _msg: &Message, _index: usize) -> NodeFunctionResult {
         unreachable!("node has no inputs");
     }
 
+    fn on_timer(&mut self, _timer: TimerId) -> NodeFunctionResult {
+        let tick = Message::new(MessageData::Int(self.count as i64));
+        self.count += 1;
+        if Some(self.count) == self.limit {
+            if let (Some(clock), Some(timer)) = (&self.clock, self.timer.take()) {
+                clock.cancel_timer(timer);
+            }
+        }
+        Ok(vec![NodeOutput::new(0, tick)])
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -86,55 +78,76 @@ impl Node for TickerNode {
     }
 }
 
+/// A period of 0 would make the timer elapse all the time.
+fn deserialize_period<'de, D>(deserializer: D) -> Result<DurationMsec, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let period = DurationMsec::deserialize(deserializer)?;
+    if period.to_duration().is_zero() {
+        return Err(D::Error::custom("ticker period must be greater than 0"));
+    }
+    Ok(period)
+}
+
 impl Drop for TickerNode {
     fn drop(&mut self) {
-        /* The thread might have already terminated because it has reached the 'limit' */
-        if self.terminate_tx.take().unwrap().send(()).is_err() {
-            return;
+        if let (Some(clock), Some(timer)) = (&self.clock, self.timer.take()) {
+            clock.cancel_timer(timer);
         }
-        if self.thread_handle.take().unwrap().join().is_err() {}
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fmt::{Debug, Formatter};
-    use std::thread;
     use std::time::Duration;
 
     use super::*;
-
-    struct TestDispatcher {
-        pub count: usize,
-    }
-
-    impl Debug for TestDispatcher {
-        fn fmt(&self, _: &mut Formatter<'_>) -> std::fmt::Result {
-            todo!()
-        }
-    }
-
-    impl EventSender for TestDispatcher {
-        fn dispatch(&mut self, _e: Event) {
-            self.count += 1;
-        }
-    }
+    use crate::clock::{Clock, Timers, VirtualClock};
 
     #[test]
     fn test_make_ticker_node() {
-        let event_sender = Arc::new(Mutex::new(TestDispatcher { count: 0 }));
+        let clock = VirtualClock::new();
+        let timers = Arc::new(Mutex::new(Timers::default()));
         let mut n: Box<dyn Node> = serde_json::from_str(
             r#"{
             "name": "node1",
             "class": "ticker",
-            "period": 500
+            "period": 500,
+            "limit": 3
         }"#,
         )
         .unwrap();
 
         assert_eq!(n.common().name, "node1");
-        n.create(Some(event_sender.clone()));
-        thread::sleep(Duration::from_millis(1200));
-        assert_eq!(event_sender.lock().unwrap().count, 2);
+        n.create(
+            None,
+            NodeClock::new(Arc::new(clock.clone()), timers.clone(), 0),
+        );
+
+        let mut ticks = vec![];
+        let mut run_timers = || loop {
+            // The node cancels its timer through the same Timers, so don't keep them locked
+            let due = timers.lock().unwrap().pop_due(clock.now());
+            let Some((_, timer)) = due else {
+                return ticks.len();
+            };
+            ticks.extend(n.on_timer(timer).unwrap());
+        };
+        clock.advance(Duration::from_millis(1200));
+        assert_eq!(run_timers(), 2);
+        // The timer is cancelled once the limit is reached
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(run_timers(), 3);
+        assert_eq!(timers.lock().unwrap().next_deadline(), None);
+        assert_eq!(ticks[2].message.data, MessageData::Int(2));
+    }
+
+    #[test]
+    fn test_ticker_zero_period() {
+        let res: Result<Box<dyn Node>, _> =
+            serde_json::from_str(r#"{"name": "node1", "class": "ticker", "period": 0}"#);
+        let err = res.unwrap_err().to_string();
+        assert!(err.contains("period must be greater than 0"), "{err}");
     }
 }